@@ -0,0 +1,187 @@
+//! Compares `sync::OnceCell` with the design it replaced: an `Option<T>`
+//! guarded by a `std::sync::Once`, a mutex serializing initializers, and a
+//! mutex and condvar for waiters.
+//!
+//! Run with `cargo bench`. Without a benchmarking harness on stable, each
+//! case is timed with `Instant`, and the best of several rounds is reported
+//! to filter out noise from the rest of the machine.
+//!
+//! Reading an initialized cell is a single `Acquire` load in both designs,
+//! which is a plain load on x86, so `get` is expected to cost the same. The
+//! differences are in the size of the cell and in creating and initializing
+//! it.
+
+use std::cell::UnsafeCell;
+use std::hint::black_box;
+use std::mem::size_of;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Condvar, Mutex, Once};
+use std::task::Waker;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use our_once_cell::sync::OnceCell;
+
+/// The previous design of `sync::OnceCell`. Only `get` and `get_or_init`
+/// are implemented; the fields prefixed with `_` are never used, and are
+/// only there so that `size_of` and `new` match the previous layout.
+struct OnceBaseline<T> {
+    inner: UnsafeCell<Option<T>>,
+    once: Once,
+    init_lock: Mutex<()>,
+    // A `PanicPolicy`, which is not available without `std`.
+    _policy: u8,
+    _poisoned: AtomicBool,
+    _poison: UnsafeCell<Option<String>>,
+    _wait_lock: Mutex<Vec<Waker>>,
+    _waiters: Condvar,
+}
+
+unsafe impl<T: Send + Sync> Sync for OnceBaseline<T> {}
+
+impl<T> OnceBaseline<T> {
+    const fn new() -> Self {
+        OnceBaseline {
+            inner: UnsafeCell::new(None),
+            once: Once::new(),
+            init_lock: Mutex::new(()),
+            _policy: 0,
+            _poisoned: AtomicBool::new(false),
+            _poison: UnsafeCell::new(None),
+            _wait_lock: Mutex::new(Vec::new()),
+            _waiters: Condvar::new(),
+        }
+    }
+
+    fn get(&self) -> Option<&T> {
+        if self.once.is_completed() {
+            unsafe { &*self.inner.get() }.as_ref()
+        } else {
+            None
+        }
+    }
+
+    fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        if let Some(val) = self.get() {
+            return val;
+        }
+        let _guard = self.init_lock.lock().unwrap();
+        let mut value = Some(f());
+        self.once
+            .call_once(|| unsafe { *self.inner.get() = value.take() });
+        self.get().unwrap()
+    }
+}
+
+const GETS: u64 = 50_000_000;
+const THREADS: u64 = 8;
+const INITS: u64 = 1_000_000;
+const ROUNDS: usize = 5;
+
+fn report(name: &str, ops: u64, elapsed: Duration) {
+    let ns = elapsed.as_secs_f64() * 1e9 / ops as f64;
+    println!("{:<40} {:>8.3} ns/op", name, ns);
+}
+
+fn time(f: impl Fn()) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    println!(
+        "{:<40} {:>8} bytes",
+        "size_of OnceCell<u64>",
+        size_of::<OnceCell<u64>>()
+    );
+    println!(
+        "{:<40} {:>8} bytes",
+        "size_of baseline<u64>",
+        size_of::<OnceBaseline<u64>>()
+    );
+
+    let cell = OnceCell::new();
+    cell.set(92u64).unwrap();
+    let baseline = OnceBaseline::new();
+    baseline.get_or_init(|| 92u64);
+
+    report(
+        "get, initialized",
+        GETS,
+        time(|| {
+            for _ in 0..GETS {
+                black_box(black_box(&cell).get());
+            }
+        }),
+    );
+    report(
+        "get, initialized (baseline)",
+        GETS,
+        time(|| {
+            for _ in 0..GETS {
+                black_box(black_box(&baseline).get());
+            }
+        }),
+    );
+
+    static SHARED: OnceCell<u64> = OnceCell::new();
+    static SHARED_BASELINE: OnceBaseline<u64> = OnceBaseline::new();
+    let per_thread = GETS / THREADS;
+    report(
+        "get_or_init, contended",
+        GETS,
+        time(|| {
+            thread::scope(|s| {
+                for _ in 0..THREADS {
+                    s.spawn(|| {
+                        for _ in 0..per_thread {
+                            black_box(SHARED.get_or_init(|| 92));
+                        }
+                    });
+                }
+            })
+        }),
+    );
+    report(
+        "get_or_init, contended (baseline)",
+        GETS,
+        time(|| {
+            thread::scope(|s| {
+                for _ in 0..THREADS {
+                    s.spawn(|| {
+                        for _ in 0..per_thread {
+                            black_box(SHARED_BASELINE.get_or_init(|| 92));
+                        }
+                    });
+                }
+            })
+        }),
+    );
+
+    report(
+        "new + get_or_init",
+        INITS,
+        time(|| {
+            for i in 0..INITS {
+                let cell = OnceCell::new();
+                black_box(cell.get_or_init(|| i));
+            }
+        }),
+    );
+    report(
+        "new + get_or_init (baseline)",
+        INITS,
+        time(|| {
+            for i in 0..INITS {
+                let cell = OnceBaseline::new();
+                black_box(cell.get_or_init(|| i));
+            }
+        }),
+    );
+}
@@ -0,0 +1,378 @@
+//! Asynchronous version of `OnceCell`.
+//!
+//! [`r#async::OnceCell`](OnceCell) is initialized by awaiting a future
+//! instead of calling a closure. Tasks that find the cell being initialized
+//! are suspended rather than blocking their thread, so an executor stays free
+//! to run unrelated tasks in the meantime.
+//!
+//! The cell only relies on [`std::task::Waker`], so it works with any
+//! executor.
+//!
+//! # Example
+//!
+//! ```
+//! use our_once_cell::r#async::OnceCell;
+//!
+//! static CONFIG: OnceCell<String> = OnceCell::new();
+//!
+//! async fn config() -> &'static str {
+//!     CONFIG.get_or_init(|| async { String::from("verbose=1") }).await
+//! }
+//! # let _ = config;
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+use crate::{sync, Void};
+
+/// A thread-safe cell which can be written to only once, and which is
+/// initialized asynchronously.
+///
+/// # Example
+///
+/// ```
+/// use our_once_cell::r#async::OnceCell;
+///
+/// async fn example() {
+///     let cell = OnceCell::new();
+///     let value = cell.get_or_init(|| async { 92 }).await;
+///     assert_eq!(value, &92);
+///     assert_eq!(cell.get(), Some(&92));
+/// }
+/// # let _ = example;
+/// ```
+pub struct OnceCell<T> {
+    value: sync::OnceCell<T>,
+    state: Mutex<State>,
+}
+
+/// Bookkeeping for tasks racing to initialize the cell.
+struct State {
+    /// Whether some task is currently running its initializer.
+    initializing: bool,
+    /// Key handed out to the next waiter that registers.
+    next_key: usize,
+    /// Tasks waiting for the running initializer to finish, in arrival
+    /// order.
+    waiters: VecDeque<(usize, Waker)>,
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(v) => f.debug_tuple("OnceCell").field(v).finish(),
+            None => f.write_str("OnceCell(Uninit)"),
+        }
+    }
+}
+
+impl<T: Clone> Clone for OnceCell<T> {
+    fn clone(&self) -> Self {
+        match self.get() {
+            Some(v) => OnceCell::from(v.clone()),
+            None => OnceCell::new(),
+        }
+    }
+}
+
+impl<T> From<T> for OnceCell<T> {
+    fn from(value: T) -> Self {
+        Self {
+            value: sync::OnceCell::from(value),
+            state: Mutex::new(State::new()),
+        }
+    }
+}
+
+impl<T: PartialEq> PartialEq for OnceCell<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl<T: Eq> Eq for OnceCell<T> {}
+
+impl<T> OnceCell<T> {
+    /// Creates a new empty cell.
+    ///
+    /// This is a `const fn`, so the cell can be used in a `static`.
+    pub const fn new() -> Self {
+        Self {
+            value: sync::OnceCell::new(),
+            state: Mutex::new(State::new()),
+        }
+    }
+
+    /// Gets a reference to the underlying value.
+    ///
+    /// Returns `None` if the cell is empty, or being initialized. This
+    /// method never blocks.
+    pub fn get(&self) -> Option<&T> {
+        self.value.get()
+    }
+
+    /// Gets a mutable reference to the underlying value.
+    ///
+    /// Returns `None` if the cell is empty.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.value.get_mut()
+    }
+
+    /// Sets the contents of this cell to `value`.
+    ///
+    /// Returns `Ok(())` if the cell was empty and `Err(value)` if it was
+    /// full. If an initializer is running concurrently, `set` does not wait
+    /// for it: the value is stored right away, and the initializer's result
+    /// is dropped once it completes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use our_once_cell::r#async::OnceCell;
+    ///
+    /// let cell = OnceCell::new();
+    /// assert_eq!(cell.set(92), Ok(()));
+    /// assert_eq!(cell.set(62), Err(62));
+    /// assert_eq!(cell.get(), Some(&92));
+    /// ```
+    pub fn set(&self, value: T) -> Result<(), T> {
+        self.value.set(value)?;
+        self.wake_waiters();
+        Ok(())
+    }
+
+    /// Gets the contents of the cell, initializing it by awaiting the future
+    /// returned by `f` if the cell was empty.
+    ///
+    /// Many tasks may call `get_or_init` concurrently, but only one of them
+    /// drives its future; the others are suspended until the value is
+    /// ready.
+    ///
+    /// # Cancellation
+    ///
+    /// The returned future may be dropped at any point, for example by a
+    /// timeout or a losing `select!` branch. If it is dropped while driving
+    /// the initializer, the cell stays empty and one of the waiting tasks is
+    /// woken up to run its own initializer instead. Waiting tasks never hang
+    /// because the task they were waiting for went away.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use our_once_cell::r#async::OnceCell;
+    ///
+    /// async fn example(cell: &OnceCell<u32>) {
+    ///     let value = cell.get_or_init(|| async { 92 }).await;
+    ///     assert_eq!(value, &92);
+    ///     let value = cell.get_or_init(|| async { unreachable!() }).await;
+    ///     assert_eq!(value, &92);
+    /// }
+    /// # let _ = example;
+    /// ```
+    pub async fn get_or_init<F, Fut>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        match self.get_or_try_init(|| async { Ok::<T, Void>(f().await) }).await {
+            Ok(val) => val,
+            Err(void) => match void {},
+        }
+    }
+
+    /// Gets the contents of the cell, initializing it by awaiting the future
+    /// returned by `f` if the cell was empty. If the cell was empty and the
+    /// future failed, an error is returned and the cell stays empty.
+    ///
+    /// When an initializer fails, one of the tasks waiting for it is woken
+    /// up and goes on to run its own initializer. The same happens when the
+    /// initializing future is cancelled, see [`get_or_init`].
+    ///
+    /// [`get_or_init`]: OnceCell::get_or_init
+    pub async fn get_or_try_init<F, Fut, E>(&self, f: F) -> Result<&T, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if let Some(val) = self.get() {
+            return Ok(val);
+        }
+
+        let guard = match (Acquire { cell: self, key: None }).await {
+            Some(guard) => guard,
+            None => return Ok(self.get().unwrap()),
+        };
+
+        // If this future is dropped while awaiting `f`, or `f` fails or
+        // panics, `guard` passes the initialization on to the next waiter.
+        let value = f().await?;
+        // A concurrent `set` may have filled the cell while we were running;
+        // in that case our value is dropped.
+        let _ = self.value.set(value);
+        guard.complete();
+        Ok(self.get().unwrap())
+    }
+
+    /// Takes the value out of this `OnceCell`, moving it back to an
+    /// uninitialized state.
+    ///
+    /// Has no effect and returns `None` if the `OnceCell` hasn't been
+    /// initialized.
+    pub fn take(&mut self) -> Option<T> {
+        std::mem::take(self).into_inner()
+    }
+
+    /// Consumes the `OnceCell`, returning the wrapped value.
+    ///
+    /// Returns `None` if the cell was empty.
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // The state is consistent at every point where we could panic.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wake_waiters(&self) {
+        let waiters = std::mem::take(&mut self.lock().waiters);
+        for (_, waker) in waiters {
+            waker.wake();
+        }
+    }
+
+    /// Wakes up the longest waiting task, so that it can take over the
+    /// initialization.
+    fn promote_waiter(&self, mut state: MutexGuard<'_, State>) {
+        if let Some((_, waker)) = state.waiters.pop_front() {
+            drop(state);
+            waker.wake();
+        }
+    }
+}
+
+impl State {
+    const fn new() -> State {
+        State {
+            initializing: false,
+            next_key: 0,
+            waiters: VecDeque::new(),
+        }
+    }
+}
+
+/// Proof that the current task is the one initializing the cell.
+///
+/// Unless it is consumed by [`InitGuard::complete`], dropping the guard
+/// hands the initialization over to one of the waiting tasks.
+struct InitGuard<'a, T> {
+    cell: &'a OnceCell<T>,
+}
+
+impl<T> InitGuard<'_, T> {
+    /// Marks the initialization as finished and wakes up every waiter. The
+    /// cell must hold a value.
+    fn complete(self) {
+        let cell = self.cell;
+        std::mem::forget(self);
+        cell.lock().initializing = false;
+        cell.wake_waiters();
+    }
+}
+
+impl<T> Drop for InitGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.cell.lock();
+        state.initializing = false;
+        self.cell.promote_waiter(state);
+    }
+}
+
+/// Resolves to an [`InitGuard`] once the current task has become the
+/// initializer of `cell`, or to `None` once the cell holds a value.
+struct Acquire<'a, T> {
+    cell: &'a OnceCell<T>,
+    /// The key of our entry in `waiters`, if we have registered. The entry
+    /// is gone if we have since been promoted.
+    key: Option<usize>,
+}
+
+impl<'a, T> Acquire<'a, T> {
+    /// Removes our entry from `waiters`. Returns `false` if there was no
+    /// entry to remove because we had been promoted.
+    fn deregister(&mut self, state: &mut State) -> bool {
+        match self.key.take() {
+            Some(key) => match state.waiters.iter().position(|(k, _)| *k == key) {
+                Some(idx) => {
+                    state.waiters.remove(idx);
+                    true
+                }
+                None => false,
+            },
+            None => true,
+        }
+    }
+}
+
+impl<'a, T> Future for Acquire<'a, T> {
+    type Output = Option<InitGuard<'a, T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let cell = self.cell;
+        let mut state = cell.lock();
+        // Checked under the lock, so that a value stored concurrently is
+        // either seen here or followed by a wake-up of our waker.
+        if cell.get().is_some() {
+            self.deregister(&mut state);
+            return Poll::Ready(None);
+        }
+        if !state.initializing {
+            self.deregister(&mut state);
+            state.initializing = true;
+            return Poll::Ready(Some(InitGuard { cell }));
+        }
+        let registered = self.key.and_then(|key| state.waiters.iter_mut().find(|(k, _)| *k == key));
+        match registered {
+            Some((_, waker)) => {
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+            }
+            // Either this is our first poll, or we were promoted but another
+            // task became the initializer before we got to run.
+            None => {
+                let key = state.next_key;
+                state.next_key = state.next_key.wrapping_add(1);
+                state.waiters.push_back((key, cx.waker().clone()));
+                self.key = Some(key);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Acquire<'_, T> {
+    fn drop(&mut self) {
+        if self.key.is_none() {
+            return;
+        }
+        let mut state = self.cell.lock();
+        // We were promoted, but are going away before taking over the
+        // initialization. Pass the promotion on, or the remaining waiters
+        // would never be woken.
+        if !self.deregister(&mut state) && !state.initializing && self.cell.get().is_none() {
+            self.cell.promote_waiter(state);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! The layout shared by `unsync::OnceVec` and `sync::OnceVec`.
+//!
+//! Elements live in a fixed array of buckets that are allocated on demand.
+//! Each bucket is twice as large as the previous one, so a vector never
+//! needs more than [`BUCKETS`] of them, and an element never moves once it
+//! has been written.
+
+/// The number of slots in the first bucket is `1 << FIRST_BUCKET_BITS`.
+const FIRST_BUCKET_BITS: u32 = 3;
+
+/// The number of buckets, enough to cover every index up to
+/// `usize::MAX - (1 << FIRST_BUCKET_BITS)`.
+pub(crate) const BUCKETS: usize = (usize::BITS - FIRST_BUCKET_BITS) as usize;
+
+/// Returns the bucket holding `index`, and the offset of `index` within it,
+/// or `None` if no bucket does.
+pub(crate) fn locate(index: usize) -> Option<(usize, usize)> {
+    let n = index.checked_add(1 << FIRST_BUCKET_BITS)?;
+    let bucket = (usize::BITS - 1 - n.leading_zeros() - FIRST_BUCKET_BITS) as usize;
+    Some((bucket, n - bucket_len(bucket)))
+}
+
+/// The number of slots in `bucket`.
+pub(crate) fn bucket_len(bucket: usize) -> usize {
+    1 << (bucket as u32 + FIRST_BUCKET_BITS)
+}
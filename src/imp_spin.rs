@@ -0,0 +1,98 @@
+//! The state machine behind `sync::OnceCell` without the standard library.
+//!
+//! It has the same states and transitions as the `std` backend, but keeps
+//! no queue: it only relies on `core` atomics, and threads that have to wait
+//! spin until the state changes.
+
+use core::hint;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+const POISONED: u8 = 3;
+
+/// How a cell that is no longer being initialized ended up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Status {
+    Complete,
+    // Only reachable through panic policies, which need `std`.
+    #[allow(dead_code)]
+    Poisoned,
+}
+
+pub(crate) struct State {
+    state: AtomicU8,
+}
+
+impl State {
+    pub(crate) const fn new() -> State {
+        State {
+            state: AtomicU8::new(INCOMPLETE),
+        }
+    }
+
+    /// Returns `true` once an initializer has completed. This is the fast
+    /// path of every read.
+    #[inline]
+    pub(crate) fn is_complete(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    /// Claims the right to initialize the cell, spinning while another
+    /// initializer is running.
+    ///
+    /// Returns `Err` with the final status if the cell has been completed or
+    /// poisoned in the meantime.
+    pub(crate) fn begin(&self) -> Result<Guard<'_>, Status> {
+        loop {
+            let exchange = self.state.compare_exchange_weak(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            );
+            match exchange {
+                Ok(_) => return Ok(Guard { state: self }),
+                Err(COMPLETE) => return Err(Status::Complete),
+                Err(POISONED) => return Err(Status::Poisoned),
+                Err(_) => hint::spin_loop(),
+            }
+        }
+    }
+
+    /// Spins until the cell is completed or poisoned.
+    pub(crate) fn wait(&self) -> Status {
+        loop {
+            match self.state.load(Ordering::Acquire) {
+                COMPLETE => return Status::Complete,
+                POISONED => return Status::Poisoned,
+                _ => hint::spin_loop(),
+            }
+        }
+    }
+}
+
+/// Proof that the current thread is running the initializer.
+///
+/// Unless it is consumed by [`Guard::complete`], dropping the guard, for
+/// example when the initializer returns an error or panics, moves the cell
+/// back to `INCOMPLETE` so that another caller can try.
+#[must_use]
+pub(crate) struct Guard<'a> {
+    state: &'a State,
+}
+
+impl Guard<'_> {
+    /// Marks the cell as initialized. The value must have been written.
+    pub(crate) fn complete(self) {
+        let this = core::mem::ManuallyDrop::new(self);
+        this.state.state.store(COMPLETE, Ordering::Release);
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        self.state.state.store(INCOMPLETE, Ordering::Release);
+    }
+}
@@ -0,0 +1,486 @@
+//! The state machine behind `sync::OnceCell`, on top of the standard
+//! library. Threads that have to wait are parked by the OS.
+//!
+//! The whole state of a cell is a single pointer-sized word. Its two low
+//! bits hold one of four states, and the remaining bits point to the most
+//! recently queued [`Waiter`]:
+//!
+//! ```text
+//!           begin                complete
+//! INCOMPLETE ----> RUNNING ------------------> COMPLETE
+//!     ^               |
+//!     |               |   panic, with PanicPolicy::Poison
+//!     +---------------+-----------------------> POISONED
+//!       error, panic
+//! ```
+//!
+//! Threads and tasks that find the cell `INCOMPLETE` or `RUNNING` and want
+//! to wait push a waiter onto the queue, which is a singly linked stack. The
+//! transition out of `RUNNING` swaps the whole word, taking the queue with
+//! it, and wakes up every waiter on it; a waiter that still has to wait then
+//! queues itself again. Reading an initialized cell is a single `Acquire`
+//! load.
+//!
+//! The queue is not intrusive: every time a thread parks, or a task is
+//! queued, a new `Arc<Waiter>` is allocated on the heap, instead of linking
+//! a node that lives on the thread's stack or in the task's future. Such a
+//! node could not be freed before it is taken off the queue, which a thread
+//! whose wait times out, or a future that is dropped, would have to wait
+//! for. Only the slow path allocates, and it is about to park anyway.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
+
+use crate::shim::thread::{self, Thread};
+use crate::shim::{self, AtomicBool, AtomicPtr, Mutex};
+
+const INCOMPLETE: usize = 0;
+const RUNNING: usize = 1;
+const COMPLETE: usize = 2;
+const POISONED: usize = 3;
+const STATE_MASK: usize = 3;
+
+/// How a cell that is no longer being initialized ended up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Status {
+    Complete,
+    Poisoned,
+}
+
+pub(crate) struct State {
+    word: AtomicPtr<Waiter>,
+}
+
+/// A queued thread or task.
+///
+/// Waiters are reference counted, shared between the queue and the thread
+/// or future that is waiting. A thread whose wait times out, or a task
+/// whose future is dropped, gives up its reference through
+/// [`State::abandon`], which takes every waiter that only the queue still
+/// references off the queue. Without that, a cell that is never
+/// initialized would keep them all.
+struct Waiter {
+    /// Set by the transition that took this waiter off the queue.
+    signaled: AtomicBool,
+    /// The waiter queued before this one. Written before the waiter is
+    /// published, and read after it is taken off the queue.
+    next: AtomicPtr<Waiter>,
+    wake: Wake,
+}
+
+enum Wake {
+    Thread(Thread),
+    /// The waker of the task, replaced if the task is polled again with a
+    /// different waker.
+    Task(Mutex<Waker>),
+}
+
+// `Waiter` is at least 4-aligned, which leaves the two low bits of a pointer
+// to it free for the state.
+const _: () = assert!(std::mem::align_of::<Waiter>() > STATE_MASK);
+
+fn state_of(word: *mut Waiter) -> usize {
+    word.addr() & STATE_MASK
+}
+
+fn queue_of(word: *mut Waiter) -> *mut Waiter {
+    word.map_addr(|addr| addr & !STATE_MASK)
+}
+
+impl State {
+    pub(crate) const fn new() -> State {
+        State {
+            word: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Returns `true` once an initializer has completed. This is the fast
+    /// path of every read.
+    #[inline]
+    pub(crate) fn is_complete(&self) -> bool {
+        state_of(self.word.load(Ordering::Acquire)) == COMPLETE
+    }
+
+    pub(crate) fn is_poisoned(&self) -> bool {
+        state_of(self.word.load(Ordering::Acquire)) == POISONED
+    }
+
+    /// Moves a poisoned cell back to `INCOMPLETE`.
+    pub(crate) fn clear_poison(&mut self) {
+        let word = self.word.get_mut();
+        if state_of(*word) == POISONED {
+            *word = ptr::null_mut();
+        }
+    }
+
+    /// Claims the right to initialize the cell, blocking while another
+    /// initializer is running.
+    ///
+    /// Returns `Err` with the final status if the cell has been completed or
+    /// poisoned in the meantime.
+    pub(crate) fn begin(&self) -> Result<Guard<'_>, Status> {
+        let mut word = self.word.load(Ordering::Acquire);
+        loop {
+            match state_of(word) {
+                COMPLETE => return Err(Status::Complete),
+                POISONED => return Err(Status::Poisoned),
+                INCOMPLETE => {
+                    let running = word.map_addr(|addr| addr | RUNNING);
+                    match self.word.compare_exchange_weak(
+                        word,
+                        running,
+                        Ordering::Acquire,
+                        Ordering::Acquire,
+                    ) {
+                        Ok(_) => return Ok(Guard { state: self }),
+                        Err(new) => word = new,
+                    }
+                }
+                _ => {
+                    self.park(word, None);
+                    word = self.word.load(Ordering::Acquire);
+                }
+            }
+        }
+    }
+
+    /// Blocks until the cell is completed or poisoned, or until `deadline`.
+    ///
+    /// Returns `None` if the deadline passed first.
+    pub(crate) fn wait(&self, deadline: Option<Instant>) -> Option<Status> {
+        let mut word = self.word.load(Ordering::Acquire);
+        loop {
+            match state_of(word) {
+                COMPLETE => return Some(Status::Complete),
+                POISONED => return Some(Status::Poisoned),
+                _ => {
+                    if !self.park(word, deadline) {
+                        return None;
+                    }
+                    word = self.word.load(Ordering::Acquire);
+                }
+            }
+        }
+    }
+
+    /// The non-blocking counterpart of [`State::wait`], for tasks.
+    ///
+    /// `waiter` is kept by the future between polls, so that a task that is
+    /// polled repeatedly stays queued only once.
+    pub(crate) fn poll_wait(
+        &self,
+        waiter: &mut Option<WaiterRef>,
+        cx: &Context<'_>,
+    ) -> Poll<Status> {
+        if let Some(WaiterRef(queued)) = waiter {
+            if !queued.register(cx.waker()) {
+                // Woken up by a transition, but we may still have to wait.
+                *waiter = None;
+            }
+        }
+        let mut word = self.word.load(Ordering::Acquire);
+        loop {
+            match state_of(word) {
+                COMPLETE => return Poll::Ready(Status::Complete),
+                POISONED => return Poll::Ready(Status::Poisoned),
+                // Still queued, with an up to date waker.
+                _ if waiter.is_some() => return Poll::Pending,
+                _ => {
+                    let task = Arc::new(Waiter::new(Wake::Task(Mutex::new(cx.waker().clone()))));
+                    match self.push(word, &task) {
+                        Ok(()) => {
+                            *waiter = Some(WaiterRef(task));
+                            return Poll::Pending;
+                        }
+                        Err(new) => word = new,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queues the current thread if the state is still `word`, and parks it
+    /// until the next transition or `deadline`.
+    ///
+    /// Returns `false` if the deadline passed first.
+    fn park(&self, word: *mut Waiter, deadline: Option<Instant>) -> bool {
+        let waiter = Arc::new(Waiter::new(Wake::Thread(thread::current())));
+        if self.push(word, &waiter).is_err() {
+            // The state changed under us; let the caller look again.
+            return true;
+        }
+        while !waiter.signaled.load(Ordering::Acquire) {
+            match deadline {
+                None => thread::park(),
+                Some(deadline) => {
+                    let now = shim::now();
+                    if now >= deadline {
+                        self.abandon(WaiterRef(waiter));
+                        return false;
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+        }
+        true
+    }
+
+    /// Pushes `waiter` onto the queue, provided the state is still `word`.
+    /// Returns the current state otherwise.
+    fn push(&self, word: *mut Waiter, waiter: &Arc<Waiter>) -> Result<(), *mut Waiter> {
+        waiter.next.store(queue_of(word), Ordering::Relaxed);
+        let queued = Arc::into_raw(Arc::clone(waiter)) as *mut Waiter;
+        let new = queued.map_addr(|addr| addr | state_of(word));
+        match self
+            .word
+            .compare_exchange(word, new, Ordering::Release, Ordering::Acquire)
+        {
+            Ok(_) => Ok(()),
+            Err(current) => {
+                // SAFETY: the queue never saw our reference; take it back.
+                drop(unsafe { Arc::from_raw(queued) });
+                Err(current)
+            }
+        }
+    }
+
+    /// Gives up on waiting, for a thread whose wait timed out or a task
+    /// whose future is dropped.
+    pub(crate) fn abandon(&self, WaiterRef(waiter): WaiterRef) {
+        let queued = !waiter.signaled.load(Ordering::Acquire);
+        drop(waiter);
+        if queued {
+            self.sweep();
+        }
+    }
+
+    /// Frees the waiters that nobody waits on any more.
+    ///
+    /// The queue is taken off the state, so that nobody else can look at
+    /// it, and the waiters that are still wanted are put back in front of
+    /// those queued in the meantime. If a transition happened in between,
+    /// it could not wake them up, so they are woken up instead.
+    fn sweep(&self) {
+        let mut word = self.word.load(Ordering::Acquire);
+        loop {
+            if queue_of(word).is_null() {
+                return;
+            }
+            let taken = ptr::null_mut::<Waiter>().with_addr(state_of(word));
+            match self
+                .word
+                .compare_exchange(word, taken, Ordering::Acquire, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(new) => word = new,
+            }
+        }
+        let state = state_of(word);
+
+        let mut queue = queue_of(word);
+        let mut kept = ptr::null_mut::<Waiter>();
+        let mut tail = ptr::null_mut::<Waiter>();
+        while !queue.is_null() {
+            // SAFETY: we took the queue, and with it its reference to each
+            // waiter.
+            let waiter = unsafe { Arc::from_raw(queue) };
+            queue = waiter.next.load(Ordering::Relaxed);
+            // Nobody can get hold of a waiter that only we reference, so
+            // that count cannot go back up.
+            if Arc::strong_count(&waiter) == 1 {
+                continue;
+            }
+            let waiter = Arc::into_raw(waiter) as *mut Waiter;
+            if tail.is_null() {
+                kept = waiter;
+            } else {
+                // SAFETY: `tail` is one of the waiters we hold.
+                unsafe { (*tail).next.store(waiter, Ordering::Relaxed) };
+            }
+            tail = waiter;
+        }
+        if kept.is_null() {
+            return;
+        }
+
+        let mut word = self.word.load(Ordering::Relaxed);
+        loop {
+            if state_of(word) != state {
+                // A failed attempt may have linked our waiters to those
+                // queued in the meantime, which the transition took.
+                // SAFETY: the waiters we kept are still ours.
+                unsafe {
+                    (*tail).next.store(ptr::null_mut(), Ordering::Relaxed);
+                    wake_all(kept);
+                }
+                return;
+            }
+            // SAFETY: `tail` is one of the waiters we hold.
+            unsafe { (*tail).next.store(queue_of(word), Ordering::Relaxed) };
+            let new = kept.map_addr(|addr| addr | state);
+            match self
+                .word
+                .compare_exchange(word, new, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(current) => word = current,
+            }
+        }
+    }
+
+    /// Returns the number of waiters in the queue.
+    ///
+    /// # Safety
+    ///
+    /// Nobody may take the queue while it is counted: no transition may
+    /// happen, and no waiter may give up. Waiters may still be pushed.
+    #[cfg(test)]
+    pub(crate) unsafe fn queued(&self) -> usize {
+        let mut queue = queue_of(self.word.load(Ordering::Acquire));
+        let mut len = 0;
+        while !queue.is_null() {
+            // SAFETY: queued waiters stay alive until the queue is taken,
+            // and pushing never changes the waiters behind the head.
+            queue = unsafe { (*queue).next.load(Ordering::Relaxed) };
+            len += 1;
+        }
+        len
+    }
+
+    /// Leaves `RUNNING` for `state`, and wakes up everybody who queued in
+    /// the meantime.
+    fn transition(&self, state: usize) {
+        let word = ptr::null_mut::<Waiter>().with_addr(state);
+        let old = self.word.swap(word, Ordering::AcqRel);
+        debug_assert_eq!(state_of(old), RUNNING);
+        // SAFETY: the swap took the queue off the state.
+        unsafe { wake_all(queue_of(old)) };
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        // Waiters that gave up may still be queued.
+        // SAFETY: `&mut self` means nobody else can push or take the queue.
+        unsafe { free_all(queue_of(*self.word.get_mut())) };
+    }
+}
+
+/// Signals and frees every waiter in the queue starting at `queue`.
+///
+/// Wakers are user code: they are called without holding the waiter's
+/// lock, since they may poll the waiting future again, and a panicking
+/// waker does not keep the rest of the queue from being woken up. The first
+/// panic is resumed once the whole queue is done, unless we are already
+/// unwinding.
+///
+/// # Safety
+///
+/// `queue` must have been taken off a `State`, so that we own the queue's
+/// reference to each waiter.
+unsafe fn wake_all(mut queue: *mut Waiter) {
+    let mut panicked = None;
+    while !queue.is_null() {
+        let waiter = Arc::from_raw(queue);
+        queue = waiter.next.load(Ordering::Relaxed);
+        waiter.signaled.store(true, Ordering::Release);
+        let woken = panic::catch_unwind(AssertUnwindSafe(move || match &waiter.wake {
+            Wake::Thread(thread) => thread.unpark(),
+            Wake::Task(waker) => {
+                let waker = waker.lock().unwrap_or_else(PoisonError::into_inner).clone();
+                waker.wake();
+            }
+        }));
+        if let Err(payload) = woken {
+            panicked.get_or_insert(payload);
+        }
+    }
+    if let Some(payload) = panicked {
+        if !std::thread::panicking() {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+/// Frees every waiter in the queue starting at `queue`, without waking
+/// them.
+///
+/// # Safety
+///
+/// Same as [`wake_all`].
+unsafe fn free_all(mut queue: *mut Waiter) {
+    while !queue.is_null() {
+        let waiter = Arc::from_raw(queue);
+        queue = waiter.next.load(Ordering::Relaxed);
+    }
+}
+
+impl Waiter {
+    fn new(wake: Wake) -> Waiter {
+        Waiter {
+            signaled: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+            wake,
+        }
+    }
+
+    /// Makes sure a queued task is woken up through `waker`. Returns `false`
+    /// if the waiter has already been signaled, and is no longer queued.
+    fn register(&self, waker: &Waker) -> bool {
+        let mut current = match &self.wake {
+            Wake::Task(waker) => waker.lock().unwrap_or_else(PoisonError::into_inner),
+            Wake::Thread(_) => unreachable!(),
+        };
+        // Checked under the lock: a transition signals before it takes the
+        // lock to wake the task, so either we see the signal, or the
+        // transition sees our new waker.
+        if self.signaled.load(Ordering::Acquire) {
+            return false;
+        }
+        if !current.will_wake(waker) {
+            *current = waker.clone();
+        }
+        true
+    }
+}
+
+/// A task's place in the queue, held by its future between polls.
+pub(crate) struct WaiterRef(Arc<Waiter>);
+
+/// Proof that the current thread is running the initializer.
+///
+/// Unless it is consumed by [`Guard::complete`] or [`Guard::poison`],
+/// dropping the guard, for example when the initializer returns an error or
+/// panics, moves the cell back to `INCOMPLETE` so that another caller can
+/// try.
+#[must_use]
+pub(crate) struct Guard<'a> {
+    state: &'a State,
+}
+
+impl Guard<'_> {
+    /// Marks the cell as initialized. The value must have been written.
+    pub(crate) fn complete(self) {
+        self.finish(COMPLETE);
+    }
+
+    /// Marks the cell as poisoned. The poison error must have been written.
+    pub(crate) fn poison(self) {
+        self.finish(POISONED);
+    }
+
+    fn finish(self, state: usize) {
+        let this = std::mem::ManuallyDrop::new(self);
+        this.state.transition(state);
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        self.state.transition(INCOMPLETE);
+    }
+}
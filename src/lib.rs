@@ -1,103 +1,1677 @@
-use std::cell::UnsafeCell;
+//! Single assignment cells.
+//!
+//! A `OnceCell` can be written to at most once and then hands out shared
+//! references to its contents for the rest of its life. This makes it useful
+//! for values that are computed lazily but never change afterwards, without
+//! having to reach for `RefCell` or `Mutex`.
+//!
+//! The crate comes in two flavours:
+//!
+//! * [`unsync::OnceCell`] is intended for single-threaded code. It is cheap,
+//!   but cannot be shared between threads.
+//! * [`sync::OnceCell`] may be shared between threads. Concurrent writers are
+//!   coordinated so that exactly one of them succeeds.
+//!
+//! Both flavours expose the same API, so switching between them is a matter
+//! of changing the import. For async code, [`r#async::OnceCell`] offers the
+//! same API with an initializer that is awaited rather than called, and the
+//! [`race`] module offers lock-free cells for when running an initializer
+//! twice is acceptable. [`sync::OnceMap`] keeps one cell per key, for values
+//! computed once per key, while [`sync::SingleFlight`] only shares a
+//! computation between the callers that overlap with it. [`unsync::OnceVec`]
+//! and [`sync::OnceVec`] are append-only vectors whose elements are never
+//! moved, and [`sync::promise`] splits a cell into a writing and a reading
+//! half. [`sync::Cached`] is a [`sync::Lazy`] whose value expires. The
+//! [`prelude`] re-exports both flavours under distinct names.
+//!
+//! # `no_std` support
+//!
+//! The crate depends on the standard library through its default `std`
+//! feature. With `default-features = false` it only uses `core`:
+//! [`sync::OnceCell`] then spins while another thread initializes it instead
+//! of parking, and everything that needs an OS or a panic runtime (timed and
+//! async waits, [`r#async`], panic policies, [`sync::OnceMap`],
+//! [`sync::SingleFlight`], [`sync::promise`], [`sync::Cached`]) is left out.
+//! The `alloc` feature brings back the heap-allocated [`race::OnceBox`] and
+//! both `OnceVec`s.
+//!
+//! # Example
+//!
+//! ```
+//! use our_once_cell::sync::OnceCell;
+//!
+//! static GLOBAL: OnceCell<Vec<i32>> = OnceCell::new();
+//! assert_eq!(GLOBAL.get_or_init(|| vec![1, 2, 3]).len(), 3);
+//!
+//! let cell = OnceCell::new();
+//! assert!(cell.get().is_none());
+//!
+//! assert_eq!(cell.set(String::from("Hello")), Ok(()));
+//! assert_eq!(cell.set(String::from("World")), Err(String::from("World")));
+//! assert_eq!(cell.get().map(String::as_str), Some("Hello"));
+//! ```
 
+// Side effects inside `debug_assert!` silently disappear in release builds.
+#![deny(clippy::debug_assert_with_mut_call)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
-mod unsync {
-    use super::UnsafeCell;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+// The tests always run on a host with the standard library.
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
 
+use core::cell::UnsafeCell;
+use core::fmt;
+
+#[cfg_attr(feature = "std", path = "imp_std.rs")]
+#[cfg_attr(not(feature = "std"), path = "imp_spin.rs")]
+mod imp;
+#[cfg(feature = "alloc")]
+mod buckets;
+#[cfg(all(test, feature = "std"))]
+mod model;
+#[cfg(feature = "std")]
+mod shim;
+
+/// An uninhabited error type, used to express infallible initialization in
+/// terms of `get_or_try_init`.
+enum Void {}
+
+/// Single-threaded version of `OnceCell`.
+///
+/// `unsync::OnceCell` is not `Sync`, so it cannot be shared between threads,
+/// but it does not pay for any synchronization either. [`unsync::Lazy`]
+/// bundles such a cell with the function that initializes it.
+pub mod unsync {
+    use super::{fmt, UnsafeCell, Void};
+    use core::cell::Cell;
+    use core::ops::{Deref, DerefMut};
+    use core::ptr;
+
+    #[cfg(feature = "alloc")]
+    mod once_vec;
+    #[cfg(feature = "alloc")]
+    pub use self::once_vec::{Iter, OnceVec};
+
+    /// A cell which can be written to only once. It is not thread safe.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use our_once_cell::unsync::OnceCell;
+    ///
+    /// let cell = OnceCell::new();
+    /// assert!(cell.get().is_none());
+    ///
+    /// let value: &String = {
+    ///     cell.set(String::from("Hello")).unwrap();
+    ///     cell.get().unwrap()
+    /// };
+    /// assert_eq!(value, "Hello");
+    /// assert!(cell.get().is_some());
+    /// ```
+    ///
+    /// # Thread safety
+    ///
+    /// `unsync::OnceCell<T>` is `Send` when `T` is, but it is never `Sync`:
+    /// `set` takes `&self` and does not synchronize.
+    ///
+    /// ```compile_fail,E0277
+    /// use our_once_cell::unsync::OnceCell;
+    ///
+    /// fn assert_sync<T: Sync>() {}
+    /// assert_sync::<OnceCell<i32>>();
+    /// ```
     pub struct OnceCell<T> {
         inner: UnsafeCell<Option<T>>,
     }
 
-    impl <T> OnceCell<T> {
-        pub fn new() -> Self {
+    impl<T> Default for OnceCell<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.get() {
+                Some(v) => f.debug_tuple("OnceCell").field(v).finish(),
+                None => f.write_str("OnceCell(Uninit)"),
+            }
+        }
+    }
+
+    impl<T: Clone> Clone for OnceCell<T> {
+        fn clone(&self) -> Self {
+            match self.get() {
+                Some(v) => OnceCell::from(v.clone()),
+                None => OnceCell::new(),
+            }
+        }
+    }
+
+    impl<T> From<T> for OnceCell<T> {
+        fn from(value: T) -> Self {
+            Self {
+                inner: UnsafeCell::new(Some(value)),
+            }
+        }
+    }
+
+    impl<T: PartialEq> PartialEq for OnceCell<T> {
+        fn eq(&self, other: &Self) -> bool {
+            self.get() == other.get()
+        }
+    }
+
+    impl<T: Eq> Eq for OnceCell<T> {}
+
+    impl<T> OnceCell<T> {
+        /// Creates a new empty cell.
+        ///
+        /// This is a `const fn`, so the cell can be used in a `static` or a
+        /// `thread_local!`.
+        pub const fn new() -> Self {
             Self {
                 inner: UnsafeCell::new(None),
             }
         }
 
+        /// Gets a reference to the underlying value.
+        ///
+        /// Returns `None` if the cell is empty.
         pub fn get(&self) -> Option<&T> {
             let ptr = self.inner.get();
-            // SAFETY
+            // SAFETY: the cell is only ever written by `set`, and only while
+            // it is empty, so a shared reference to a value is never aliased
+            // by a write. The `&Option<T>` itself does not outlive this call.
             unsafe { &*ptr }.as_ref()
         }
 
+        /// Gets a mutable reference to the underlying value.
+        ///
+        /// Returns `None` if the cell is empty.
         pub fn get_mut(&mut self) -> Option<&mut T> {
             let ptr = self.inner.get();
-            // SAFETY
+            // SAFETY: `&mut self` rules out any outstanding reference from
+            // `get`.
             unsafe { &mut *ptr }.as_mut()
         }
 
+        /// Sets the contents of this cell to `value`.
+        ///
+        /// Returns `Ok(())` if the cell was empty and `Err(value)` if it was
+        /// full.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::unsync::OnceCell;
+        ///
+        /// let cell = OnceCell::new();
+        /// assert!(cell.get().is_none());
+        ///
+        /// assert_eq!(cell.set(92), Ok(()));
+        /// assert_eq!(cell.set(62), Err(62));
+        ///
+        /// assert_eq!(cell.get(), Some(&92));
+        /// ```
         pub fn set(&self, value: T) -> Result<(), T> {
             if self.get().is_some() {
                 return Err(value);
             }
-            let r = unsafe { &mut *self.inner.get() };
-            let old = std::mem::replace(r, Some(value));
-            debug_assert!(old.is_none());
+            // SAFETY: the cell is empty, so `get` has not handed out any
+            // reference to a value, and the `&Option<T>` it created above is
+            // gone. Writing through the raw pointer, instead of through a
+            // `&mut Option<T>`, keeps a write from ever being derived from a
+            // unique borrow of the whole cell.
+            unsafe { ptr::write(self.inner.get(), Some(value)) };
             Ok(())
         }
+
+        /// Gets the contents of the cell, initializing it with `f` if the cell
+        /// was empty.
+        ///
+        /// # Panics
+        ///
+        /// If `f` panics, the panic is propagated to the caller, and the cell
+        /// remains uninitialized.
+        ///
+        /// It is an error to reentrantly initialize the cell from `f`. Doing
+        /// so results in a panic, and the value stored by the inner call is
+        /// kept.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::unsync::OnceCell;
+        ///
+        /// let cell = OnceCell::new();
+        /// let value = cell.get_or_init(|| 92);
+        /// assert_eq!(value, &92);
+        /// let value = cell.get_or_init(|| unreachable!());
+        /// assert_eq!(value, &92);
+        /// ```
+        pub fn get_or_init<F>(&self, f: F) -> &T
+        where
+            F: FnOnce() -> T,
+        {
+            match self.get_or_try_init(|| Ok::<T, Void>(f())) {
+                Ok(val) => val,
+                Err(void) => match void {},
+            }
+        }
+
+        /// Gets the contents of the cell, initializing it with `f` if the cell
+        /// was empty. If the cell was empty and `f` failed, an error is
+        /// returned and the cell stays empty, so a later call may retry.
+        ///
+        /// # Panics
+        ///
+        /// If `f` panics, the panic is propagated to the caller, and the cell
+        /// remains uninitialized.
+        ///
+        /// It is an error to reentrantly initialize the cell from `f`. Doing
+        /// so results in a panic, and the value stored by the inner call is
+        /// kept.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::unsync::OnceCell;
+        ///
+        /// let cell = OnceCell::new();
+        /// assert_eq!(cell.get_or_try_init(|| Err(())), Err(()));
+        /// assert!(cell.get().is_none());
+        /// let value = cell.get_or_try_init(|| -> Result<i32, ()> { Ok(92) });
+        /// assert_eq!(value, Ok(&92));
+        /// assert_eq!(cell.get(), Some(&92))
+        /// ```
+        pub fn get_or_try_init<F, E>(&self, f: F) -> Result<&T, E>
+        where
+            F: FnOnce() -> Result<T, E>,
+        {
+            if let Some(val) = self.get() {
+                return Ok(val);
+            }
+            let val = f()?;
+            // `set` refuses to overwrite a value, so if `f` has initialized
+            // the cell behind our back, the reference it handed out stays
+            // valid. Keeping the inner value silently would hide a logic error
+            // though, so report it loudly instead.
+            assert!(
+                self.set(val).is_ok(),
+                "OnceCell: the cell was reentrantly initialized by its own initializer"
+            );
+            Ok(self.get().unwrap())
+        }
+
+        /// Takes the value out of this `OnceCell`, moving it back to an
+        /// uninitialized state.
+        ///
+        /// Has no effect and returns `None` if the `OnceCell` hasn't been
+        /// initialized.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::unsync::OnceCell;
+        ///
+        /// let mut cell: OnceCell<String> = OnceCell::new();
+        /// assert_eq!(cell.take(), None);
+        ///
+        /// let mut cell = OnceCell::new();
+        /// cell.set("hello".to_string()).unwrap();
+        /// assert_eq!(cell.take(), Some("hello".to_string()));
+        /// assert_eq!(cell.get(), None);
+        /// ```
+        pub fn take(&mut self) -> Option<T> {
+            core::mem::take(self).into_inner()
+        }
+
+        /// Consumes the `OnceCell`, returning the wrapped value.
+        ///
+        /// Returns `None` if the cell was empty.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::unsync::OnceCell;
+        ///
+        /// let cell: OnceCell<String> = OnceCell::new();
+        /// assert_eq!(cell.into_inner(), None);
+        ///
+        /// let cell = OnceCell::new();
+        /// cell.set("hello".to_string()).unwrap();
+        /// assert_eq!(cell.into_inner(), Some("hello".to_string()));
+        /// ```
+        pub fn into_inner(self) -> Option<T> {
+            self.inner.into_inner()
+        }
+    }
+
+    /// A value which is initialized on the first access.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use our_once_cell::unsync::Lazy;
+    ///
+    /// let lazy: Lazy<i32> = Lazy::new(|| {
+    ///     println!("initializing");
+    ///     92
+    /// });
+    /// println!("ready");
+    /// println!("{}", *lazy);
+    /// println!("{}", *lazy);
+    ///
+    /// // Prints:
+    /// //   ready
+    /// //   initializing
+    /// //   92
+    /// //   92
+    /// ```
+    pub struct Lazy<T, F = fn() -> T> {
+        cell: OnceCell<T>,
+        init: Cell<Option<F>>,
+    }
+
+    impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Lazy").field("cell", &self.cell).field("init", &"..").finish()
+        }
+    }
+
+    impl<T: Default> Default for Lazy<T> {
+        /// Creates a new lazy value using `Default` as the initializing
+        /// function.
+        fn default() -> Lazy<T> {
+            Lazy::new(T::default)
+        }
+    }
+
+    impl<T, F> Lazy<T, F> {
+        /// Creates a new lazy value with the given initializing function.
+        pub const fn new(init: F) -> Lazy<T, F> {
+            Lazy {
+                cell: OnceCell::new(),
+                init: Cell::new(Some(init)),
+            }
+        }
+
+        /// Consumes this `Lazy` returning the stored value.
+        ///
+        /// Returns `Ok(value)` if `Lazy` is initialized and `Err(f)`
+        /// otherwise.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::unsync::Lazy;
+        ///
+        /// let lazy = Lazy::new(|| 92);
+        /// assert_eq!(*lazy, 92);
+        /// assert_eq!(Lazy::into_value(lazy).ok(), Some(92));
+        ///
+        /// let lazy: Lazy<i32> = Lazy::new(|| 92);
+        /// assert!(Lazy::into_value(lazy).is_err());
+        /// ```
+        pub fn into_value(this: Lazy<T, F>) -> Result<T, F> {
+            let cell = this.cell;
+            let init = this.init;
+            cell.into_inner().ok_or_else(|| {
+                init.take().unwrap_or_else(|| panic!("Lazy instance has previously been poisoned"))
+            })
+        }
+    }
+
+    impl<T, F: FnOnce() -> T> Lazy<T, F> {
+        /// Forces the evaluation of this lazy value and returns a reference
+        /// to the result.
+        ///
+        /// This is equivalent to the `Deref` impl, but is explicit.
+        ///
+        /// # Panics
+        ///
+        /// If the initializing function panics, the panic is propagated and
+        /// the `Lazy` is poisoned: every further access panics as well.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::unsync::Lazy;
+        ///
+        /// let lazy = Lazy::new(|| 92);
+        ///
+        /// assert_eq!(Lazy::force(&lazy), &92);
+        /// assert_eq!(&*lazy, &92);
+        /// ```
+        pub fn force(this: &Lazy<T, F>) -> &T {
+            this.cell.get_or_init(|| match this.init.take() {
+                Some(f) => f(),
+                None => panic!("Lazy instance has previously been poisoned"),
+            })
+        }
+
+        /// Gets the reference to the result of this lazy value if it was
+        /// initialized, otherwise returns `None`.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::unsync::Lazy;
+        ///
+        /// let lazy = Lazy::new(|| 92);
+        ///
+        /// assert_eq!(Lazy::get(&lazy), None);
+        /// assert_eq!(&*lazy, &92);
+        /// assert_eq!(Lazy::get(&lazy), Some(&92));
+        /// ```
+        pub fn get(this: &Lazy<T, F>) -> Option<&T> {
+            this.cell.get()
+        }
+
+        /// Gets the mutable reference to the result of this lazy value if it
+        /// was initialized, otherwise returns `None`.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::unsync::Lazy;
+        ///
+        /// let mut lazy = Lazy::new(|| 92);
+        ///
+        /// assert_eq!(Lazy::get_mut(&mut lazy), None);
+        /// assert_eq!(*lazy, 92);
+        /// *Lazy::get_mut(&mut lazy).unwrap() += 1;
+        /// assert_eq!(*lazy, 93);
+        /// ```
+        pub fn get_mut(this: &mut Lazy<T, F>) -> Option<&mut T> {
+            this.cell.get_mut()
+        }
+    }
+
+    impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+        type Target = T;
+        fn deref(&self) -> &T {
+            Lazy::force(self)
+        }
+    }
+
+    impl<T, F: FnOnce() -> T> DerefMut for Lazy<T, F> {
+        fn deref_mut(&mut self) -> &mut T {
+            Lazy::force(self);
+            self.cell.get_mut().unwrap_or_else(|| unreachable!())
+        }
     }
 }
 
+/// Thread-safe version of `OnceCell`.
+///
+/// `sync::OnceCell` can be shared between threads, for example through an
+/// `Arc` or a `static`. Writers are coordinated through a single atomic state
+/// word, so at most one of them ever succeeds, and reading an initialized
+/// cell costs one `Acquire` load. With the `std` feature, threads that have
+/// to wait for a writer are parked; without it, they spin. [`sync::Lazy`]
+/// bundles such a cell with the function that initializes it, and is a
+/// drop-in replacement for `lazy_static!`.
+pub mod sync {
+    use super::{fmt, imp, UnsafeCell, Void};
+    #[cfg(feature = "std")]
+    use alloc::boxed::Box;
+    use core::cell::Cell;
+    use core::ops::{Deref, DerefMut};
+    use core::panic::{RefUnwindSafe, UnwindSafe};
+    use core::ptr;
+    #[cfg(feature = "std")]
+    use std::{
+        any::Any,
+        future::Future,
+        hash::Hash,
+        panic::{self, AssertUnwindSafe},
+        pin::Pin,
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    };
+
+    #[cfg(feature = "std")]
+    mod once_map;
+    #[cfg(feature = "std")]
+    pub use self::once_map::OnceMap;
+    #[cfg(feature = "alloc")]
+    mod once_vec;
+    #[cfg(feature = "alloc")]
+    pub use self::once_vec::{Iter, OnceVec};
+    #[cfg(feature = "std")]
+    mod single_flight;
+    #[cfg(feature = "std")]
+    pub use self::single_flight::SingleFlight;
+    #[cfg(feature = "std")]
+    mod promise;
+    #[cfg(feature = "std")]
+    pub use self::promise::{promise, Broken, Completion, Promise};
+    #[cfg(feature = "std")]
+    mod cached;
+    #[cfg(feature = "std")]
+    pub use self::cached::{Cached, Clock, SystemClock};
 
-mod sync {
-    use super::UnsafeCell;
-    use std::option::Option::Some;
-    use std::sync::Once;
+    /// What a [`OnceCell`] or [`Lazy`] does when its initializer panics.
+    ///
+    /// In every case the panic itself is propagated to the thread that ran
+    /// the initializer.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::panic;
+    /// use our_once_cell::sync::{OnceCell, PanicPolicy};
+    ///
+    /// let cell = OnceCell::with_policy(PanicPolicy::Poison);
+    /// let res = panic::catch_unwind(|| {
+    ///     cell.get_or_init(|| -> i32 { panic!("config file missing") });
+    /// });
+    /// assert!(res.is_err());
+    ///
+    /// assert!(cell.is_poisoned());
+    /// assert_eq!(cell.poison_error().unwrap().message(), "config file missing");
+    /// ```
+    #[cfg(feature = "std")]
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub enum PanicPolicy {
+        /// The cell stays uninitialized, and the next access runs an
+        /// initializer again. This is the default for [`OnceCell`].
+        #[default]
+        Retry,
+        /// The cell is poisoned: every further attempt to initialize it, and
+        /// every thread waiting for it, panics with a [`PoisonError`] that
+        /// carries the original panic message. This is the default for
+        /// [`Lazy`].
+        Poison,
+        /// The process is aborted, after the panic message has been printed.
+        Abort,
+    }
+
+    /// The error reported by a cell whose initializer has panicked under
+    /// [`PanicPolicy::Poison`].
+    #[cfg(feature = "std")]
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct PoisonError {
+        message: String,
+    }
+
+    #[cfg(feature = "std")]
+    impl PoisonError {
+        fn from_payload(payload: &(dyn Any + Send)) -> PoisonError {
+            let message = if let Some(s) = payload.downcast_ref::<&'static str>() {
+                String::from(*s)
+            } else if let Some(s) = payload.downcast_ref::<String>() {
+                s.clone()
+            } else {
+                String::from("Box<dyn Any>")
+            };
+            PoisonError { message }
+        }
+
+        /// The message of the panic that poisoned the cell.
+        pub fn message(&self) -> &str {
+            &self.message
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl fmt::Display for PoisonError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "OnceCell instance has previously been poisoned: {}", self.message)
+        }
+    }
 
+    #[cfg(feature = "std")]
+    impl std::error::Error for PoisonError {}
+
+    /// A thread-safe cell which can be written to only once.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use our_once_cell::sync::OnceCell;
+    ///
+    /// let cell = Arc::new(OnceCell::new());
+    ///
+    /// let handle = {
+    ///     let cell = Arc::clone(&cell);
+    ///     std::thread::spawn(move || cell.set(92).is_ok())
+    /// };
+    /// let other = cell.set(62).is_ok();
+    /// let winner = handle.join().unwrap();
+    ///
+    /// assert!(winner ^ other);
+    /// assert!(cell.get().is_some());
+    /// ```
+    ///
+    /// # Thread safety
+    ///
+    /// `sync::OnceCell<T>` is `Send` when `T: Send`, and `Sync` when
+    /// `T: Send + Sync`. `T: Sync` is needed because every thread gets a `&T`,
+    /// and `T: Send` because whichever thread wins the race moves its value
+    /// into the cell, from where the owner of the cell eventually drops it.
+    ///
+    /// A cell holding a `!Sync` value cannot be shared:
+    ///
+    /// ```compile_fail,E0277
+    /// use std::cell::Cell;
+    /// use our_once_cell::sync::OnceCell;
+    ///
+    /// fn assert_sync<T: Sync>() {}
+    /// assert_sync::<OnceCell<Cell<i32>>>();
+    /// ```
+    ///
+    /// Neither can a cell holding a `!Send` value:
+    ///
+    /// ```compile_fail,E0277
+    /// use std::rc::Rc;
+    /// use our_once_cell::sync::OnceCell;
+    ///
+    /// let cell: &'static OnceCell<Rc<i32>> = Box::leak(Box::new(OnceCell::new()));
+    /// std::thread::spawn(move || {
+    ///     let _ = cell.set(Rc::new(92));
+    /// });
+    /// ```
+    ///
+    /// And such a cell cannot be sent to another thread either:
+    ///
+    /// ```compile_fail,E0277
+    /// use std::rc::Rc;
+    /// use our_once_cell::sync::OnceCell;
+    ///
+    /// let cell = OnceCell::from(Rc::new(92));
+    /// std::thread::spawn(move || drop(cell));
+    /// ```
     pub struct OnceCell<T> {
+        /// Written once by the thread that moved `state` to running, and
+        /// only read after `state` is complete.
         inner: UnsafeCell<Option<T>>,
-        once: Once,
+        state: imp::State,
+        #[cfg(feature = "std")]
+        policy: PanicPolicy,
+        /// Written by the initializer that poisons `state`, before it does
+        /// so, and only read after `state` is poisoned. Boxed, so that the
+        /// message only takes space in cells that are actually poisoned.
+        #[cfg(feature = "std")]
+        poison: UnsafeCell<Option<Box<PoisonError>>>,
+    }
+
+    // SAFETY: sharing the cell hands out `&T` to every thread, and lets any
+    // of them move a `T` in that is later dropped elsewhere.
+    unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+    // SAFETY: sending the cell sends the `T` inside of it.
+    unsafe impl<T: Send> Send for OnceCell<T> {}
+
+    // A panicking initializer never leaves the cell half-initialized: it is
+    // either retried or reported through the poison.
+    impl<T: RefUnwindSafe + UnwindSafe> RefUnwindSafe for OnceCell<T> {}
+    impl<T: UnwindSafe> UnwindSafe for OnceCell<T> {}
+
+    impl<T> Default for OnceCell<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.get() {
+                Some(v) => f.debug_tuple("OnceCell").field(v).finish(),
+                #[cfg(feature = "std")]
+                None if self.is_poisoned() => f.write_str("OnceCell(Poisoned)"),
+                None => f.write_str("OnceCell(Uninit)"),
+            }
+        }
+    }
+
+    impl<T: Clone> Clone for OnceCell<T> {
+        fn clone(&self) -> Self {
+            let cell = self.empty_like();
+            if let Some(v) = self.get() {
+                let _ = cell.set(v.clone());
+            }
+            cell
+        }
+    }
+
+    impl<T> From<T> for OnceCell<T> {
+        fn from(value: T) -> Self {
+            let cell = Self::new();
+            let _ = cell.set(value);
+            cell
+        }
+    }
+
+    impl<T: PartialEq> PartialEq for OnceCell<T> {
+        fn eq(&self, other: &Self) -> bool {
+            self.get() == other.get()
+        }
     }
 
-    unsafe impl <T> Sync for OnceCell<T> {}
+    impl<T: Eq> Eq for OnceCell<T> {}
 
     impl<T> OnceCell<T> {
-        pub fn new() -> Self {
+        /// Creates a new empty cell.
+        ///
+        /// This is a `const fn`, so the cell can be used in a `static`.
+        ///
+        /// ```
+        /// use our_once_cell::sync::OnceCell;
+        ///
+        /// static CONFIG: OnceCell<String> = OnceCell::new();
+        ///
+        /// let config = CONFIG.get_or_init(|| String::from("verbose=1"));
+        /// assert_eq!(config, "verbose=1");
+        /// ```
+        pub const fn new() -> Self {
             Self {
                 inner: UnsafeCell::new(None),
-                once: Once::new(),
+                state: imp::State::new(),
+                #[cfg(feature = "std")]
+                policy: PanicPolicy::Retry,
+                #[cfg(feature = "std")]
+                poison: UnsafeCell::new(None),
             }
         }
 
+        /// Creates a new empty cell, which handles a panicking initializer
+        /// according to `policy`.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::sync::{OnceCell, PanicPolicy};
+        ///
+        /// static TOKEN: OnceCell<String> = OnceCell::with_policy(PanicPolicy::Poison);
+        /// assert_eq!(TOKEN.policy(), PanicPolicy::Poison);
+        /// ```
+        #[cfg(feature = "std")]
+        pub const fn with_policy(policy: PanicPolicy) -> Self {
+            let mut cell = Self::new();
+            cell.policy = policy;
+            cell
+        }
+
+        /// Returns the policy this cell applies when its initializer panics.
+        #[cfg(feature = "std")]
+        pub fn policy(&self) -> PanicPolicy {
+            self.policy
+        }
+
+        /// Returns `true` if an initializer has panicked under
+        /// [`PanicPolicy::Poison`].
+        #[cfg(feature = "std")]
+        pub fn is_poisoned(&self) -> bool {
+            self.state.is_poisoned()
+        }
+
+        /// Returns the error describing why this cell is poisoned, or `None`
+        /// if it is not.
+        #[cfg(feature = "std")]
+        pub fn poison_error(&self) -> Option<&PoisonError> {
+            if self.is_poisoned() {
+                // SAFETY: the error was written before the state was released
+                // as poisoned, and is only modified again through `&mut self`.
+                unsafe { &*self.poison.get() }.as_deref()
+            } else {
+                None
+            }
+        }
+
+        /// Clears the poison, so the cell can be initialized again.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use std::panic;
+        /// use our_once_cell::sync::{OnceCell, PanicPolicy};
+        ///
+        /// let mut cell = OnceCell::with_policy(PanicPolicy::Poison);
+        /// let _ = panic::catch_unwind(|| cell.get_or_init(|| -> i32 { panic!() }));
+        /// assert!(cell.is_poisoned());
+        ///
+        /// cell.clear_poison();
+        /// assert_eq!(cell.get_or_init(|| 92), &92);
+        /// ```
+        #[cfg(feature = "std")]
+        pub fn clear_poison(&mut self) {
+            *self.poison.get_mut() = None;
+            self.state.clear_poison();
+        }
+
+        /// Gets a reference to the underlying value.
+        ///
+        /// Returns `None` if the cell is empty, or being initialized. This
+        /// method never blocks.
+        #[inline]
         pub fn get(&self) -> Option<&T> {
-            if self.once.is_completed() {
-                unsafe { &(*self.inner.get()) }.as_ref()
+            if self.state.is_complete() {
+                // SAFETY: we have just observed the state as complete.
+                Some(unsafe { self.get_unchecked() })
             } else {
                 None
             }
         }
 
+        /// Gets a reference to the value of a complete cell.
+        ///
+        /// # Safety
+        ///
+        /// The caller must have observed the state as complete.
+        unsafe fn get_unchecked(&self) -> &T {
+            // SAFETY: a complete state was released after the value was
+            // written, and the value is never written again through `&self`,
+            // so this shared borrow is never aliased by a write.
+            let inner = &*self.inner.get();
+            debug_assert!(inner.is_some());
+            inner.as_ref().unwrap_unchecked()
+        }
+
+        /// Gets a mutable reference to the underlying value.
+        ///
+        /// Returns `None` if the cell is empty. Since this method borrows the
+        /// cell mutably, it never blocks.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::sync::OnceCell;
+        ///
+        /// let mut cell = OnceCell::from(92);
+        /// *cell.get_mut().unwrap() += 1;
+        /// assert_eq!(cell.get(), Some(&93));
+        /// ```
+        pub fn get_mut(&mut self) -> Option<&mut T> {
+            // With `&mut self` there is no concurrent initializer, and a
+            // value is only ever stored by the initializer that completes
+            // the state.
+            self.inner.get_mut().as_mut()
+        }
+
+        /// Sets the contents of this cell to `value`.
+        ///
+        /// If several threads race to set the cell, exactly one of them
+        /// succeeds and the others get their value back. If an initializer
+        /// is running, `set` blocks until it has finished, and only stores
+        /// `value` if the initializer failed.
+        ///
+        /// Returns `Ok(())` if the cell was empty and `Err(value)` if it was
+        /// full or poisoned.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::sync::OnceCell;
+        ///
+        /// let cell = OnceCell::new();
+        /// assert!(cell.get().is_none());
+        ///
+        /// assert_eq!(cell.set(92), Ok(()));
+        /// assert_eq!(cell.set(62), Err(62));
+        ///
+        /// assert_eq!(cell.get(), Some(&92));
+        /// ```
         pub fn set(&self, value: T) -> Result<(), T> {
-            if self.once.is_completed() {
-                return Err(value)
+            if self.state.is_complete() {
+                return Err(value);
+            }
+            match self.state.begin() {
+                Ok(guard) => {
+                    // SAFETY: we hold the guard, so no other thread reads or
+                    // writes the value until we complete the state, and the
+                    // cell is not complete, so no `&T` has been handed out.
+                    unsafe { ptr::write(self.inner.get(), Some(value)) };
+                    guard.complete();
+                    Ok(())
+                }
+                Err(_) => Err(value),
             }
+        }
 
-            let mut value = Some(value);
-            self.once.call_once(|| {
-                let inner = unsafe { &mut *self.inner.get() };
-                debug_assert!(std::mem::replace(inner, value.take()).is_none());
-            });
+        /// Gets the contents of the cell, initializing it with `f` if the cell
+        /// was empty.
+        ///
+        /// Many threads may call `get_or_init` concurrently with different
+        /// initializing functions, but it is guaranteed that only one function
+        /// will be executed. The other threads block until the value is ready
+        /// and then observe it.
+        ///
+        /// # Panics
+        ///
+        /// If `f` panics, the panic is propagated to the caller, and the cell
+        /// reacts according to its [`PanicPolicy`]. By default it remains
+        /// uninitialized, and the next caller runs its own initializer.
+        ///
+        /// Panics if the cell is poisoned.
+        ///
+        /// It is an error to reentrantly initialize the cell from `f`. The
+        /// exact outcome is unspecified; currently it deadlocks.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use std::sync::Arc;
+        /// use std::sync::atomic::{AtomicUsize, Ordering};
+        /// use our_once_cell::sync::OnceCell;
+        ///
+        /// let cell = Arc::new(OnceCell::new());
+        /// let calls = Arc::new(AtomicUsize::new(0));
+        ///
+        /// let handles: Vec<_> = (0..4)
+        ///     .map(|i| {
+        ///         let cell = Arc::clone(&cell);
+        ///         let calls = Arc::clone(&calls);
+        ///         std::thread::spawn(move || {
+        ///             *cell.get_or_init(|| {
+        ///                 calls.fetch_add(1, Ordering::SeqCst);
+        ///                 i
+        ///             })
+        ///         })
+        ///     })
+        ///     .collect();
+        ///
+        /// let values: Vec<i32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        /// assert!(values.iter().all(|v| v == &values[0]));
+        /// assert_eq!(calls.load(Ordering::SeqCst), 1);
+        /// ```
+        #[inline]
+        pub fn get_or_init<F>(&self, f: F) -> &T
+        where
+            F: FnOnce() -> T,
+        {
+            match self.get_or_try_init(|| Ok::<T, Void>(f())) {
+                Ok(val) => val,
+                Err(void) => match void {},
+            }
+        }
+
+        /// Gets the contents of the cell, initializing it with `f` if the cell
+        /// was empty. If the cell was empty and `f` failed, an error is
+        /// returned and the cell stays empty, so a later call may retry.
+        ///
+        /// Initializers are serialized: while one thread runs `f`, other
+        /// callers block. If `f` fails, the next blocked caller runs its own
+        /// initializer.
+        ///
+        /// # Panics
+        ///
+        /// If `f` panics, the panic is propagated to the caller, and the cell
+        /// reacts according to its [`PanicPolicy`].
+        ///
+        /// Panics if the cell is poisoned.
+        ///
+        /// It is an error to reentrantly initialize the cell from `f`. The
+        /// exact outcome is unspecified; currently it deadlocks.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::sync::OnceCell;
+        ///
+        /// let cell = OnceCell::new();
+        /// assert_eq!(cell.get_or_try_init(|| Err(())), Err(()));
+        /// assert!(cell.get().is_none());
+        /// let value = cell.get_or_try_init(|| -> Result<i32, ()> { Ok(92) });
+        /// assert_eq!(value, Ok(&92));
+        /// assert_eq!(cell.get(), Some(&92))
+        /// ```
+        #[inline]
+        pub fn get_or_try_init<F, E>(&self, f: F) -> Result<&T, E>
+        where
+            F: FnOnce() -> Result<T, E>,
+        {
+            if let Some(val) = self.get() {
+                return Ok(val);
+            }
+            self.initialize(f)
+        }
+
+        /// The slow path of [`OnceCell::get_or_try_init`], kept out of line
+        /// so that the fast path stays small enough to be inlined.
+        #[cold]
+        fn initialize<F, E>(&self, f: F) -> Result<&T, E>
+        where
+            F: FnOnce() -> Result<T, E>,
+        {
+            // Initializers take turns: `begin` blocks while another one is
+            // running. If ours fails, dropping `guard` lets the next one in.
+            let guard = match self.state.begin() {
+                Ok(guard) => guard,
+                Err(status) => return Ok(self.finished(status)),
+            };
+
+            // Without `std` there is no way to catch the panic, but dropping
+            // `guard` while unwinding still lets the next caller retry.
+            #[cfg(feature = "std")]
+            let value = match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(res) => res?,
+                Err(payload) => self.initializer_panicked(guard, payload),
+            };
+            #[cfg(not(feature = "std"))]
+            let value = f()?;
+
+            // SAFETY: we hold the guard, so no other thread reads or writes
+            // the value until we complete the state, and the cell is not
+            // complete, so no `&T` has been handed out.
+            unsafe { ptr::write(self.inner.get(), Some(value)) };
+            guard.complete();
+            // SAFETY: we have just completed the state ourselves.
+            Ok(unsafe { self.get_unchecked() })
+        }
+
+        /// Blocks the current thread until the cell is initialized, then
+        /// returns a reference to the value.
+        ///
+        /// This does not run any initializer itself; it waits for another
+        /// thread to call `set` or `get_or_init`.
+        ///
+        /// Without the `std` feature, the thread spins instead of being
+        /// parked.
+        ///
+        /// # Panics
+        ///
+        /// Panics if the cell is or becomes poisoned while waiting.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::sync::OnceCell;
+        ///
+        /// static CELL: OnceCell<u32> = OnceCell::new();
+        ///
+        /// std::thread::scope(|s| {
+        ///     s.spawn(|| assert_eq!(CELL.wait(), &92));
+        ///     s.spawn(|| CELL.set(92).unwrap());
+        /// });
+        /// ```
+        pub fn wait(&self) -> &T {
+            if let Some(val) = self.get() {
+                return val;
+            }
+            #[cfg(feature = "std")]
+            let status = self.state.wait(None).unwrap_or_else(|| unreachable!());
+            #[cfg(not(feature = "std"))]
+            let status = self.state.wait();
+            self.finished(status)
+        }
+
+        /// Blocks the current thread until the cell is initialized, or until
+        /// `timeout` has elapsed.
+        ///
+        /// Returns `None` if the cell is still empty when the timeout
+        /// expires.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use std::time::Duration;
+        /// use our_once_cell::sync::OnceCell;
+        ///
+        /// let cell: OnceCell<u32> = OnceCell::new();
+        /// assert_eq!(cell.wait_timeout(Duration::from_millis(1)), None);
+        /// cell.set(92).unwrap();
+        /// assert_eq!(cell.wait_timeout(Duration::from_millis(1)), Some(&92));
+        /// ```
+        #[cfg(feature = "std")]
+        pub fn wait_timeout(&self, timeout: Duration) -> Option<&T> {
+            match crate::shim::now().checked_add(timeout) {
+                Some(deadline) => self.wait_deadline(deadline),
+                // A deadline this far out is as good as none at all.
+                None => Some(self.wait()),
+            }
+        }
+
+        /// Blocks the current thread until the cell is initialized, or until
+        /// `deadline` is reached.
+        ///
+        /// Returns `None` if the cell is still empty at the deadline.
+        ///
+        /// # Panics
+        ///
+        /// Panics if the cell is or becomes poisoned while waiting.
+        #[cfg(feature = "std")]
+        pub fn wait_deadline(&self, deadline: Instant) -> Option<&T> {
+            if let Some(val) = self.get() {
+                return Some(val);
+            }
+            let status = self.state.wait(Some(deadline))?;
+            Some(self.finished(status))
+        }
+
+        /// Returns a future that resolves once the cell is initialized.
+        ///
+        /// Like [`wait`](OnceCell::wait), this does not run any initializer,
+        /// but instead of blocking the thread it suspends the task until
+        /// another thread calls `set` or `get_or_init`. This lets async code
+        /// consume a value that is produced by thread-based code.
+        ///
+        /// The future panics when polled if the cell is poisoned.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use std::sync::Arc;
+        /// use std::thread;
+        /// use our_once_cell::sync::OnceCell;
+        ///
+        /// async fn consume(cell: Arc<OnceCell<String>>) -> usize {
+        ///     cell.wait_async().await.len()
+        /// }
+        ///
+        /// let cell = Arc::new(OnceCell::new());
+        /// let producer = {
+        ///     let cell = Arc::clone(&cell);
+        ///     thread::spawn(move || cell.set(String::from("hello")).unwrap())
+        /// };
+        ///
+        /// assert_eq!(block_on(consume(cell)), 5);
+        /// producer.join().unwrap();
+        /// # // A minimal executor that parks the thread until the future is woken.
+        /// # fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+        /// #     use std::task::{Context, Poll, Wake, Waker};
+        /// #     struct ThreadWaker(thread::Thread);
+        /// #     impl Wake for ThreadWaker {
+        /// #         fn wake(self: Arc<Self>) {
+        /// #             self.0.unpark();
+        /// #         }
+        /// #     }
+        /// #     let mut fut = Box::pin(fut);
+        /// #     let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        /// #     let mut cx = Context::from_waker(&waker);
+        /// #     loop {
+        /// #         match fut.as_mut().poll(&mut cx) {
+        /// #             Poll::Ready(output) => return output,
+        /// #             Poll::Pending => thread::park(),
+        /// #         }
+        /// #     }
+        /// # }
+        /// ```
+        #[cfg(feature = "std")]
+        pub fn wait_async(&self) -> impl Future<Output = &T> + '_ {
+            WaitAsync { cell: self, waiter: None }
+        }
+
+        /// Returns the value once the cell is no longer being initialized,
+        /// or panics with the poison error.
+        fn finished(&self, status: imp::Status) -> &T {
+            match status {
+                // SAFETY: `Complete` is only reported for a complete state.
+                imp::Status::Complete => unsafe { self.get_unchecked() },
+                #[cfg(feature = "std")]
+                imp::Status::Poisoned => self.check_poison(),
+                #[cfg(not(feature = "std"))]
+                imp::Status::Poisoned => unreachable!(),
+            }
+        }
+
+        /// Panics with the poison error. The cell must be poisoned.
+        #[cfg(feature = "std")]
+        fn check_poison(&self) -> ! {
+            match self.poison_error() {
+                Some(err) => panic!("{}", err),
+                None => unreachable!(),
+            }
+        }
+
+        /// Applies the panic policy after the initializer panicked with
+        /// `payload`, then resumes the panic.
+        #[cfg(feature = "std")]
+        fn initializer_panicked(&self, guard: imp::Guard<'_>, payload: Box<dyn Any + Send>) -> ! {
+            match self.policy {
+                PanicPolicy::Retry => drop(guard),
+                PanicPolicy::Poison => {
+                    // SAFETY: we hold the guard, so nobody else is reading or
+                    // writing `poison` until we poison the state.
+                    unsafe { *self.poison.get() = Some(Box::new(PoisonError::from_payload(&*payload))) };
+                    guard.poison();
+                }
+                PanicPolicy::Abort => std::process::abort(),
+            }
+            panic::resume_unwind(payload)
+        }
+
+        /// Takes the value out of this `OnceCell`, moving it back to an
+        /// uninitialized state.
+        ///
+        /// Has no effect and returns `None` if the `OnceCell` hasn't been
+        /// initialized.
+        ///
+        /// Since this method borrows the cell mutably, no other thread can be
+        /// initializing it concurrently. The cell is reset to an empty state
+        /// without poison, but keeps its panic policy, so it can be
+        /// initialized again afterwards.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::sync::OnceCell;
+        ///
+        /// let mut cell: OnceCell<String> = OnceCell::new();
+        /// assert_eq!(cell.take(), None);
+        ///
+        /// let mut cell = OnceCell::new();
+        /// cell.set("hello".to_string()).unwrap();
+        /// assert_eq!(cell.take(), Some("hello".to_string()));
+        /// assert_eq!(cell.get(), None);
+        /// ```
+        pub fn take(&mut self) -> Option<T> {
+            let empty = self.empty_like();
+            core::mem::replace(self, empty).into_inner()
+        }
+
+        /// Consumes the `OnceCell`, returning the wrapped value.
+        ///
+        /// Returns `None` if the cell was empty.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::sync::OnceCell;
+        ///
+        /// let cell: OnceCell<String> = OnceCell::new();
+        /// assert_eq!(cell.into_inner(), None);
+        ///
+        /// let cell = OnceCell::new();
+        /// cell.set("hello".to_string()).unwrap();
+        /// assert_eq!(cell.into_inner(), Some("hello".to_string()));
+        /// ```
+        pub fn into_inner(self) -> Option<T> {
+            self.inner.into_inner()
+        }
+
+        /// Creates an empty cell with the same panic policy as this one.
+        fn empty_like(&self) -> Self {
+            #[cfg(feature = "std")]
+            return OnceCell::with_policy(self.policy);
+            #[cfg(not(feature = "std"))]
+            return OnceCell::new();
+        }
+
+        /// Returns the number of threads and tasks queued on the cell.
+        ///
+        /// # Safety
+        ///
+        /// The cell must not be initialized, and no waiter may time out or
+        /// be dropped, while the queue is counted.
+        #[cfg(all(test, feature = "std"))]
+        pub(crate) unsafe fn queued_waiters(&self) -> usize {
+            self.state.queued()
+        }
+    }
+
+    /// Future returned by [`OnceCell::wait_async`].
+    #[cfg(feature = "std")]
+    struct WaitAsync<'a, T> {
+        cell: &'a OnceCell<T>,
+        /// Our place in the cell's queue, once we have registered.
+        waiter: Option<imp::WaiterRef>,
+    }
+
+    #[cfg(feature = "std")]
+    impl<'a, T> Future for WaitAsync<'a, T> {
+        type Output = &'a T;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<&'a T> {
+            let this = self.get_mut();
+            let cell = this.cell;
+            if let Some(val) = cell.get() {
+                return Poll::Ready(val);
+            }
+            cell.state.poll_wait(&mut this.waiter, cx).map(|status| cell.finished(status))
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl<T> Drop for WaitAsync<'_, T> {
+        fn drop(&mut self) {
+            if let Some(waiter) = self.waiter.take() {
+                self.cell.state.abandon(waiter);
+            }
+        }
+    }
+
+    /// A value which is initialized on the first access.
+    ///
+    /// This type is thread-safe and can be used in statics. Concurrent
+    /// accesses during initialization block until the single running
+    /// initializer has finished.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use our_once_cell::sync::Lazy;
+    ///
+    /// static HASHMAP: Lazy<HashMap<u32, &'static str>> = Lazy::new(|| {
+    ///     println!("initializing");
+    ///     let mut m = HashMap::new();
+    ///     m.insert(13, "Spica");
+    ///     m.insert(74, "Hoyten");
+    ///     m
+    /// });
+    ///
+    /// println!("ready");
+    /// std::thread::spawn(|| {
+    ///     println!("{:?}", HASHMAP.get(&13));
+    /// }).join().unwrap();
+    /// println!("{:?}", HASHMAP.get(&74));
+    ///
+    /// // Prints:
+    /// //   ready
+    /// //   initializing
+    /// //   Some("Spica")
+    /// //   Some("Hoyten")
+    /// ```
+    ///
+    /// # Thread safety
+    ///
+    /// `Lazy<T, F>` is `Sync` when `OnceCell<T>` is and `F: Send`: the
+    /// initializer is moved out and run by whichever thread forces the value
+    /// first. A `Lazy` with a `!Send` initializer cannot be shared:
+    ///
+    /// ```compile_fail,E0277
+    /// use std::rc::Rc;
+    /// use our_once_cell::sync::Lazy;
+    ///
+    /// fn assert_sync<T: Sync>(_: &T) {}
+    ///
+    /// let rc = Rc::new(92);
+    /// let lazy: Lazy<i32, _> = Lazy::new(move || *rc);
+    /// assert_sync(&lazy);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If the initializer panics, the panic is propagated to the thread that
+    /// forced the value, and the `Lazy` reacts according to its
+    /// [`PanicPolicy`]. A `Lazy` created with [`Lazy::new`] uses
+    /// [`PanicPolicy::Poison`], as the failed attempt consumes its
+    /// initializer: every further access panics with the original message.
+    /// Use [`Lazy::with_policy`] to choose another policy.
+    pub struct Lazy<T, F = fn() -> T> {
+        cell: OnceCell<T>,
+        init: Cell<Option<F>>,
+        /// Clones the initializer, so that a retried `Lazy` can run a copy
+        /// and keep the original should the copy panic.
+        #[cfg(feature = "std")]
+        clone_init: Option<fn(&F) -> F>,
+    }
+
+    impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Lazy").field("cell", &self.cell).field("init", &"..").finish()
+        }
+    }
+
+    // SAFETY: `init` is only touched from within the cell's initializer,
+    // which runs on one thread at a time, or through `&mut`/ownership.
+    unsafe impl<T, F: Send> Sync for Lazy<T, F> where OnceCell<T>: Sync {}
+
+    impl<T: Default> Default for Lazy<T> {
+        /// Creates a new lazy value using `Default` as the initializing
+        /// function.
+        fn default() -> Lazy<T> {
+            Lazy::new(T::default)
+        }
+    }
 
-            match value {
-                None => Ok(()),
-                Some(v) => {
-                    debug_assert!(self.once.is_completed());
-                    Err(v)
-                },
+    impl<T, F> Lazy<T, F> {
+        /// Creates a new lazy value with the given initializing function.
+        ///
+        /// This is a `const fn`, so the value can be used in a `static`.
+        pub const fn new(f: F) -> Lazy<T, F> {
+            Lazy {
+                #[cfg(feature = "std")]
+                cell: OnceCell::with_policy(PanicPolicy::Poison),
+                #[cfg(not(feature = "std"))]
+                cell: OnceCell::new(),
+                init: Cell::new(Some(f)),
+                #[cfg(feature = "std")]
+                clone_init: None,
             }
         }
+
+        /// Creates a new lazy value with the given initializing function,
+        /// which handles a panicking initializer according to `policy`.
+        ///
+        /// With [`PanicPolicy::Retry`], each attempt runs a clone of `f`, so a
+        /// transient panic does not use the initializer up.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use std::panic;
+        /// use std::sync::atomic::{AtomicUsize, Ordering};
+        /// use our_once_cell::sync::{Lazy, PanicPolicy};
+        ///
+        /// static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+        /// static FLAKY: Lazy<usize> = Lazy::with_policy(
+        ///     || match ATTEMPTS.fetch_add(1, Ordering::SeqCst) {
+        ///         0 => panic!("transient failure"),
+        ///         n => n,
+        ///     },
+        ///     PanicPolicy::Retry,
+        /// );
+        ///
+        /// assert!(panic::catch_unwind(|| *FLAKY).is_err());
+        /// assert_eq!(*FLAKY, 1);
+        /// ```
+        #[cfg(feature = "std")]
+        pub const fn with_policy(f: F, policy: PanicPolicy) -> Lazy<T, F>
+        where
+            F: Clone,
+        {
+            Lazy {
+                cell: OnceCell::with_policy(policy),
+                init: Cell::new(Some(f)),
+                clone_init: Some(F::clone),
+            }
+        }
+
+        /// Returns `true` if the initializer has panicked and the `Lazy` is
+        /// poisoned.
+        #[cfg(feature = "std")]
+        pub fn is_poisoned(this: &Lazy<T, F>) -> bool {
+            this.cell.is_poisoned()
+        }
+
+        /// Returns the error describing why this `Lazy` is poisoned, or
+        /// `None` if it is not.
+        #[cfg(feature = "std")]
+        pub fn poison_error(this: &Lazy<T, F>) -> Option<&PoisonError> {
+            this.cell.poison_error()
+        }
+
+        /// Clears the poison, so that the next access runs `f`.
+        ///
+        /// The initializer that panicked was used up, so a new one has to be
+        /// supplied.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use std::panic::{self, AssertUnwindSafe};
+        /// use our_once_cell::sync::Lazy;
+        ///
+        /// let mut lazy: Lazy<String> = Lazy::new(|| panic!("config missing"));
+        /// assert!(panic::catch_unwind(AssertUnwindSafe(|| lazy.len())).is_err());
+        /// assert!(Lazy::is_poisoned(&lazy));
+        ///
+        /// Lazy::clear_poison(&mut lazy, || String::from("default"));
+        /// assert_eq!(*lazy, "default");
+        /// ```
+        #[cfg(feature = "std")]
+        pub fn clear_poison(this: &mut Lazy<T, F>, f: F) {
+            this.cell.clear_poison();
+            this.init.set(Some(f));
+        }
+
+        /// Consumes this `Lazy` returning the stored value.
+        ///
+        /// Returns `Ok(value)` if `Lazy` is initialized and `Err(f)`
+        /// otherwise.
+        ///
+        /// # Panics
+        ///
+        /// Panics if the `Lazy` is poisoned, that is if its initializer has
+        /// panicked.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::sync::Lazy;
+        ///
+        /// let lazy = Lazy::new(|| 92);
+        /// assert_eq!(*lazy, 92);
+        /// assert_eq!(Lazy::into_value(lazy).ok(), Some(92));
+        ///
+        /// let lazy: Lazy<i32> = Lazy::new(|| 92);
+        /// assert!(Lazy::into_value(lazy).is_err());
+        /// ```
+        pub fn into_value(this: Lazy<T, F>) -> Result<T, F> {
+            let cell = this.cell;
+            let init = this.init;
+            #[cfg(feature = "std")]
+            if let Some(err) = cell.poison_error() {
+                panic!("{}", err);
+            }
+            cell.into_inner().ok_or_else(|| {
+                init.take().unwrap_or_else(|| panic!("Lazy instance has previously been poisoned"))
+            })
+        }
+    }
+
+    impl<T, F: FnOnce() -> T> Lazy<T, F> {
+        /// Forces the evaluation of this lazy value and returns a reference
+        /// to the result.
+        ///
+        /// This is equivalent to the `Deref` impl, but is explicit.
+        ///
+        /// # Panics
+        ///
+        /// Panics if the initializer panics, or if the `Lazy` is poisoned.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::sync::Lazy;
+        ///
+        /// let lazy = Lazy::new(|| 92);
+        ///
+        /// assert_eq!(Lazy::force(&lazy), &92);
+        /// assert_eq!(&*lazy, &92);
+        /// ```
+        pub fn force(this: &Lazy<T, F>) -> &T {
+            this.cell.get_or_init(|| {
+                let f = match this.init.take() {
+                    Some(f) => f,
+                    None => panic!("Lazy instance has previously been poisoned"),
+                };
+                #[cfg(feature = "std")]
+                if let Some(clone) = this.clone_init {
+                    if this.cell.policy == PanicPolicy::Retry {
+                        let attempt = clone(&f);
+                        this.init.set(Some(f));
+                        return attempt();
+                    }
+                }
+                f()
+            })
+        }
+
+        /// Gets the reference to the result of this lazy value if it was
+        /// initialized, otherwise returns `None`.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::sync::Lazy;
+        ///
+        /// let lazy = Lazy::new(|| 92);
+        ///
+        /// assert_eq!(Lazy::get(&lazy), None);
+        /// assert_eq!(&*lazy, &92);
+        /// assert_eq!(Lazy::get(&lazy), Some(&92));
+        /// ```
+        pub fn get(this: &Lazy<T, F>) -> Option<&T> {
+            this.cell.get()
+        }
+
+        /// Gets the mutable reference to the result of this lazy value if it
+        /// was initialized, otherwise returns `None`.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use our_once_cell::sync::Lazy;
+        ///
+        /// let mut lazy = Lazy::new(|| 92);
+        ///
+        /// assert_eq!(Lazy::get_mut(&mut lazy), None);
+        /// assert_eq!(*lazy, 92);
+        /// *Lazy::get_mut(&mut lazy).unwrap() += 1;
+        /// assert_eq!(*lazy, 93);
+        /// ```
+        pub fn get_mut(this: &mut Lazy<T, F>) -> Option<&mut T> {
+            this.cell.get_mut()
+        }
+    }
+
+    impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+        type Target = T;
+        fn deref(&self) -> &T {
+            Lazy::force(self)
+        }
+    }
+
+    impl<T, F: FnOnce() -> T> DerefMut for Lazy<T, F> {
+        fn deref_mut(&mut self) -> &mut T {
+            Lazy::force(self);
+            self.cell.get_mut().unwrap_or_else(|| unreachable!())
+        }
     }
 }
 
+#[cfg(feature = "std")]
+pub mod r#async;
+pub mod race;
+
+/// Convenience re-exports of both cell flavours under distinct names.
+///
+/// ```
+/// use our_once_cell::prelude::*;
+///
+/// let local: UnsyncOnceCell<u32> = UnsyncOnceCell::new();
+/// let shared: SyncOnceCell<u32> = SyncOnceCell::new();
+/// assert_eq!(local.set(1), shared.set(1));
+/// ```
+pub mod prelude {
+    pub use crate::sync::Lazy as SyncLazy;
+    #[cfg(feature = "std")]
+    pub use crate::sync::PanicPolicy;
+    pub use crate::sync::OnceCell as SyncOnceCell;
+    pub use crate::unsync::Lazy as UnsyncLazy;
+    pub use crate::unsync::OnceCell as UnsyncOnceCell;
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(not(feature = "std"))]
+    use std::{format, string::String, thread_local, vec, vec::Vec};
+    #[cfg(all(feature = "alloc", not(feature = "std")))]
+    use std::{boxed::Box, string::ToString};
 
     #[test]
     fn it_works() {
-        let mut once: unsync::OnceCell<String> = unsync::OnceCell::new();
+        let once: unsync::OnceCell<String> = unsync::OnceCell::new();
 
         assert!(once.get().is_none());
         assert!(once.set(String::new()).is_ok());
@@ -111,17 +1685,1726 @@ mod tests {
         let once = Arc::new(sync::OnceCell::new());
 
         let one = Arc::clone(&once);
-        std::thread::spawn(move || {
-            println!("{:?}", one.set(String::from("Hello")));
+        let one = std::thread::spawn(move || {
+            one.set(String::from("Hello"))
         });
 
         let two = Arc::clone(&once);
-        std::thread::spawn(move || {
-            println!("{:?}", two.set(String::from("World")));
+        let two = std::thread::spawn(move || {
+            two.set(String::from("World"))
+        });
+
+        let results = [one.join().unwrap(), two.join().unwrap()];
+        let winner = match results {
+            [Ok(()), Err(loser)] => {
+                assert_eq!(loser, "World");
+                "Hello"
+            }
+            [Err(loser), Ok(())] => {
+                assert_eq!(loser, "Hello");
+                "World"
+            }
+            other => panic!("exactly one `set` must win: {:?}", other),
+        };
+
+        assert_eq!(once.get().map(String::as_str), Some(winner));
+    }
+
+    #[test]
+    fn sync_set_stores_value() {
+        // `set` used to store the value from inside a `debug_assert!`, which
+        // left the cell empty in release builds.
+        let cell = sync::OnceCell::new();
+        assert_eq!(cell.set(92), Ok(()));
+        assert_eq!(cell.get(), Some(&92));
+        assert_eq!(cell.set(62), Err(62));
+        assert_eq!(cell.get_or_init(|| 62), &92);
+        assert_eq!(sync::OnceCell::from(92).get(), Some(&92));
+    }
+
+    #[test]
+    fn unsync_get_or_init() {
+        let cell = unsync::OnceCell::new();
+        let mut calls = 0;
+        assert_eq!(cell.get_or_init(|| { calls += 1; 92 }), &92);
+        assert_eq!(cell.get_or_init(|| { calls += 1; 62 }), &92);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "reentrantly initialized")]
+    fn unsync_reentrant_init() {
+        let cell = unsync::OnceCell::new();
+        cell.get_or_init(|| {
+            let inner = cell.get_or_init(|| String::from("inner"));
+            assert_eq!(inner, "inner");
+            String::from("outer")
+        });
+    }
+
+    #[test]
+    fn sync_get_or_init_runs_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Arc, Barrier};
+
+        let cell = Arc::new(sync::OnceCell::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let cell = Arc::clone(&cell);
+                let calls = Arc::clone(&calls);
+                let barrier = Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    *cell.get_or_init(|| {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                        i
+                    })
+                })
+            })
+            .collect();
+
+        let values: Vec<i32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(values.iter().all(|&v| Some(&v) == cell.get()));
+    }
+
+    #[test]
+    fn get_or_try_init_retries_after_error() {
+        let unsync_cell: unsync::OnceCell<String> = unsync::OnceCell::new();
+        assert_eq!(unsync_cell.get_or_try_init(|| Err("nope")), Err("nope"));
+        assert!(unsync_cell.get().is_none());
+        assert_eq!(unsync_cell.get_or_try_init(|| Ok::<_, ()>(String::from("yes"))).unwrap(), "yes");
+
+        let sync_cell: sync::OnceCell<String> = sync::OnceCell::new();
+        assert_eq!(sync_cell.get_or_try_init(|| Err("nope")), Err("nope"));
+        assert!(sync_cell.get().is_none());
+        assert_eq!(sync_cell.get_or_try_init(|| Ok::<_, ()>(String::from("yes"))).unwrap(), "yes");
+        assert_eq!(sync_cell.get_or_try_init(|| Err("late")).unwrap(), "yes");
+    }
+
+    #[test]
+    fn sync_get_or_init_retries_after_panic() {
+        let cell = sync::OnceCell::new();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            cell.get_or_init(|| panic!("transient"));
+        }));
+        assert!(res.is_err());
+        assert!(cell.get().is_none());
+        assert_eq!(cell.get_or_init(|| 92), &92);
+    }
+
+    #[test]
+    fn sync_static_from_many_threads() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CELL: sync::OnceCell<String> = sync::OnceCell::new();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    if i % 2 == 0 {
+                        let _ = CELL.set(format!("set by {}", i));
+                    }
+                    CELL.get_or_init(|| {
+                        CALLS.fetch_add(1, Ordering::SeqCst);
+                        format!("init by {}", i)
+                    })
+                    .as_str()
+                })
+            })
+            .collect();
+
+        let values: Vec<&'static str> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert!(values.iter().all(|v| *v == values[0]));
+        assert!(CALLS.load(Ordering::SeqCst) <= 1);
+        assert_eq!(CELL.get().map(String::as_str), Some(values[0]));
+    }
+
+    #[test]
+    fn unsync_thread_local() {
+        thread_local! {
+            static CELL: unsync::OnceCell<u32> = const { unsync::OnceCell::new() };
+        }
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| std::thread::spawn(move || CELL.with(|cell| *cell.get_or_init(|| i))))
+            .collect();
+        let values: Vec<u32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(values, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn unsync_lazy() {
+        let calls = std::cell::Cell::new(0);
+        let mut lazy = unsync::Lazy::new(|| {
+            calls.set(calls.get() + 1);
+            String::from("hello")
         });
 
-        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(unsync::Lazy::get(&lazy), None);
+        assert_eq!(calls.get(), 0);
+        assert_eq!(lazy.len(), 5);
+        assert_eq!(&*lazy, "hello");
+        assert_eq!(calls.get(), 1);
+
+        lazy.push_str(", world");
+        assert_eq!(unsync::Lazy::into_value(lazy).ok().as_deref(), Some("hello, world"));
+    }
+
+    #[test]
+    fn unsync_lazy_poisoned() {
+        let lazy: unsync::Lazy<i32> = unsync::Lazy::new(|| panic!("boom"));
+        for _ in 0..2 {
+            let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| *lazy));
+            assert!(res.is_err());
+        }
+        assert_eq!(unsync::Lazy::get(&lazy), None);
+    }
+
+    #[test]
+    fn sync_lazy_static() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static LAZY: sync::Lazy<Vec<usize>> = sync::Lazy::new(|| {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            vec![1, 2, 3]
+        });
 
-        println!("{:?}", once.get());
+        let handles: Vec<_> = (0..8).map(|_| std::thread::spawn(|| LAZY.len())).collect();
+        for h in handles {
+            assert_eq!(h.join().unwrap(), 3);
+        }
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        assert_eq!(sync::Lazy::get(&LAZY), Some(&vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn sync_lazy_poisoned() {
+        let lazy: sync::Lazy<i32> = sync::Lazy::new(|| panic!("boom"));
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                let res = s.spawn(|| *lazy).join();
+                assert!(res.is_err());
+            }
+        });
+        assert_eq!(sync::Lazy::get(&lazy), None);
+    }
+
+    /// Spins until `queued` counts `n` waiters, so that we know they are
+    /// blocked rather than about to look at the cell.
+    #[cfg(feature = "std")]
+    fn wait_until_queued(n: usize, queued: impl Fn() -> usize) {
+        while queued() < n {
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn sync_wait_wakes_all_waiters() {
+        use std::sync::Barrier;
+
+        let cell = sync::OnceCell::new();
+        let barrier = Barrier::new(5);
+        std::thread::scope(|s| {
+            let waiters: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        barrier.wait();
+                        *cell.wait()
+                    })
+                })
+                .collect();
+            barrier.wait();
+            // The spinning backend has no queue; its waiters may still be
+            // about to look at the cell.
+            // SAFETY: the cell is only initialized below.
+            #[cfg(feature = "std")]
+            wait_until_queued(4, || unsafe { cell.queued_waiters() });
+            cell.set(92).unwrap();
+            for waiter in waiters {
+                assert_eq!(waiter.join().unwrap(), 92);
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_wait_timeout() {
+        use std::time::{Duration, Instant};
+
+        let cell = sync::OnceCell::new();
+        let start = Instant::now();
+        assert_eq!(cell.wait_timeout(Duration::from_millis(20)), None);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(cell.wait_deadline(Instant::now()), None);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                // SAFETY: the cell is only initialized below, and the
+                // waiter's deadline is far away.
+                wait_until_queued(1, || unsafe { cell.queued_waiters() });
+                cell.get_or_init(|| 92);
+            });
+            assert_eq!(cell.wait_timeout(Duration::from_secs(60)), Some(&92));
+        });
+        assert_eq!(cell.wait_timeout(Duration::MAX), Some(&92));
+    }
+
+    #[test]
+    fn sync_set_waits_for_running_initializer() {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let cell = sync::OnceCell::new();
+        let (started, running) = mpsc::channel();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                cell.get_or_init(|| {
+                    started.send(()).unwrap();
+                    std::thread::sleep(Duration::from_millis(20));
+                    92
+                })
+            });
+            running.recv().unwrap();
+            assert_eq!(cell.set(62), Err(62));
+        });
+        assert_eq!(cell.get(), Some(&92));
+
+        let cell = sync::OnceCell::new();
+        let (started, running) = mpsc::channel();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                cell.get_or_try_init(|| {
+                    started.send(()).unwrap();
+                    std::thread::sleep(Duration::from_millis(20));
+                    Err(())
+                })
+            });
+            running.recv().unwrap();
+            assert_eq!(cell.set(62), Ok(()));
+        });
+        assert_eq!(cell.get(), Some(&62));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_abandoned_waiters() {
+        use std::time::Duration;
+
+        let cell = sync::OnceCell::new();
+        assert_eq!(cell.wait_timeout(Duration::from_millis(1)), None);
+        let (woken, waker) = FlagWaker::new();
+        let mut abandoned = Box::pin(cell.wait_async());
+        assert!(poll_once(&mut abandoned, &waker).is_pending());
+        drop(abandoned);
+
+        let mut waiting = Box::pin(cell.wait_async());
+        assert!(poll_once(&mut waiting, &waker).is_pending());
+        // A failed initializer wakes everybody up, and the task has to queue
+        // itself again.
+        assert_eq!(cell.get_or_try_init(|| Err(())), Err(()));
+        assert!(woken.take());
+        assert!(poll_once(&mut waiting, &waker).is_pending());
+
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| *cell.wait());
+            // Queued behind the task.
+            // SAFETY: the cell is only initialized below.
+            wait_until_queued(2, || unsafe { cell.queued_waiters() });
+            cell.set(92).unwrap();
+            assert_eq!(waiter.join().unwrap(), 92);
+        });
+        assert!(woken.take());
+        assert_eq!(poll_once(&mut waiting, &waker), std::task::Poll::Ready(&92));
+    }
+
+    /// Runs a future to completion on the current thread.
+    #[cfg(feature = "std")]
+    fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct ThreadWaker(std::thread::Thread);
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let mut fut = Box::pin(fut);
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    /// A future that returns `Pending` once before completing, forcing a
+    /// task switch in the middle of an initializer.
+    #[cfg(feature = "std")]
+    fn yield_now() -> impl std::future::Future<Output = ()> {
+        let mut yielded = false;
+        std::future::poll_fn(move |cx| {
+            if yielded {
+                return std::task::Poll::Ready(());
+            }
+            yielded = true;
+            cx.waker().wake_by_ref();
+            std::task::Poll::Pending
+        })
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn async_get_or_init() {
+        let cell = r#async::OnceCell::new();
+        assert_eq!(block_on(cell.get_or_init(|| async {
+            yield_now().await;
+            92
+        })), &92);
+        assert_eq!(block_on(cell.get_or_init(|| async { unreachable!() })), &92);
+        assert_eq!(cell.set(62), Err(62));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn async_get_or_init_runs_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Barrier;
+
+        let cell = r#async::OnceCell::new();
+        let calls = AtomicUsize::new(0);
+        let barrier = Barrier::new(8);
+        std::thread::scope(|s| {
+            let tasks: Vec<_> = (0..8)
+                .map(|i| {
+                    let (cell, calls, barrier) = (&cell, &calls, &barrier);
+                    s.spawn(move || {
+                        barrier.wait();
+                        *block_on(cell.get_or_init(|| async move {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            for _ in 0..10 {
+                                yield_now().await;
+                            }
+                            i
+                        }))
+                    })
+                })
+                .collect();
+            let values: Vec<i32> = tasks.into_iter().map(|t| t.join().unwrap()).collect();
+            assert!(values.iter().all(|v| Some(v) == cell.get()));
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn async_get_or_try_init_retries() {
+        let cell: r#async::OnceCell<u32> = r#async::OnceCell::new();
+        assert_eq!(block_on(cell.get_or_try_init(|| async { Err("nope") })), Err("nope"));
+        assert_eq!(cell.get(), None);
+        assert_eq!(block_on(cell.get_or_try_init(|| async { Ok::<_, ()>(92) })), Ok(&92));
+    }
+
+    /// A waker that records whether it has been woken.
+    #[cfg(feature = "std")]
+    struct FlagWaker(std::sync::atomic::AtomicBool);
+
+    #[cfg(feature = "std")]
+    impl std::task::Wake for FlagWaker {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl FlagWaker {
+        fn new() -> (std::sync::Arc<FlagWaker>, std::task::Waker) {
+            let flag = std::sync::Arc::new(FlagWaker(std::sync::atomic::AtomicBool::new(false)));
+            (flag.clone(), std::task::Waker::from(flag))
+        }
+
+        fn take(&self) -> bool {
+            self.0.swap(false, std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn poll_once<F: std::future::Future>(
+        fut: &mut std::pin::Pin<Box<F>>,
+        waker: &std::task::Waker,
+    ) -> std::task::Poll<F::Output> {
+        fut.as_mut().poll(&mut std::task::Context::from_waker(waker))
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn async_cancelled_initializer_promotes_waiter() {
+        const YIELDS: usize = 3;
+
+        // Cancel the initializing task after each of its await points.
+        for cancel_after in 1..=YIELDS {
+            let cell = r#async::OnceCell::new();
+
+            let (_, init_waker) = FlagWaker::new();
+            let mut init = Box::pin(cell.get_or_init(|| async {
+                for _ in 0..YIELDS {
+                    yield_now().await;
+                }
+                1
+            }));
+            for _ in 0..cancel_after {
+                assert!(poll_once(&mut init, &init_waker).is_pending());
+            }
+
+            let (woken, waiter_waker) = FlagWaker::new();
+            let mut waiter = Box::pin(cell.get_or_init(|| async { 2 }));
+            assert!(poll_once(&mut waiter, &waiter_waker).is_pending());
+            assert!(!woken.take());
+
+            drop(init);
+            assert!(woken.take(), "waiter not woken after cancelling at {}", cancel_after);
+            assert_eq!(poll_once(&mut waiter, &waiter_waker), std::task::Poll::Ready(&2));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn async_cancelled_waiters_pass_promotion_on() {
+        let cell = r#async::OnceCell::new();
+        let (_, waker) = FlagWaker::new();
+
+        let mut init = Box::pin(cell.get_or_init(|| async {
+            yield_now().await;
+            1
+        }));
+        assert!(poll_once(&mut init, &waker).is_pending());
+
+        let flags: Vec<_> = (0..3).map(|_| FlagWaker::new()).collect();
+        let mut waiters: Vec<_> = (0..3)
+            .map(|i| Box::pin(cell.get_or_init(move || async move { 10 + i })))
+            .collect();
+        for (waiter, (_, waker)) in waiters.iter_mut().zip(&flags) {
+            assert!(poll_once(waiter, waker).is_pending());
+        }
+
+        // A waiter cancelled while waiting simply leaves the queue.
+        let second = waiters.remove(1);
+        drop(second);
+
+        // The first waiter is promoted, but cancelled before it runs; the
+        // promotion moves on to the last one.
+        drop(init);
+        assert!(flags[0].0.take());
+        assert!(!flags[2].0.take());
+        let first = waiters.remove(0);
+        drop(first);
+        assert!(flags[2].0.take());
+
+        let mut last = waiters.remove(0);
+        assert_eq!(poll_once(&mut last, &flags[2].1), std::task::Poll::Ready(&12));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn async_failed_initializer_promotes_waiter() {
+        let cell: r#async::OnceCell<u32> = r#async::OnceCell::new();
+        let (_, waker) = FlagWaker::new();
+
+        let mut failing = Box::pin(cell.get_or_try_init(|| async {
+            yield_now().await;
+            Err("nope")
+        }));
+        assert!(poll_once(&mut failing, &waker).is_pending());
+
+        let (woken, waiter_waker) = FlagWaker::new();
+        let mut waiter = Box::pin(cell.get_or_init(|| async { 92 }));
+        assert!(poll_once(&mut waiter, &waiter_waker).is_pending());
+
+        assert_eq!(poll_once(&mut failing, &waker), std::task::Poll::Ready(Err("nope")));
+        assert!(woken.take());
+        assert_eq!(poll_once(&mut waiter, &waiter_waker), std::task::Poll::Ready(&92));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_wait_async() {
+        let cell = sync::OnceCell::new();
+
+        let (woken, waker) = FlagWaker::new();
+        let mut first = Box::pin(cell.wait_async());
+        let mut second = Box::pin(cell.wait_async());
+        assert!(poll_once(&mut first, &waker).is_pending());
+        assert!(poll_once(&mut first, &waker).is_pending());
+        assert!(poll_once(&mut second, &waker).is_pending());
+        assert!(!woken.take());
+
+        cell.get_or_init(|| 92);
+        assert!(woken.take());
+        assert_eq!(poll_once(&mut first, &waker), std::task::Poll::Ready(&92));
+        assert_eq!(poll_once(&mut second, &waker), std::task::Poll::Ready(&92));
+        assert_eq!(block_on(cell.wait_async()), &92);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_wait_async_from_thread() {
+        let cell: sync::OnceCell<String> = sync::OnceCell::new();
+        std::thread::scope(|s| {
+            let consumer = s.spawn(|| block_on(cell.wait_async()).len());
+            // The future has returned `Pending` once it is queued.
+            // SAFETY: the cell is only initialized below.
+            wait_until_queued(1, || unsafe { cell.queued_waiters() });
+            cell.set(String::from("hello")).unwrap();
+            assert_eq!(consumer.join().unwrap(), 5);
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_wait_async_woken_by_inline_poll() {
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::sync::{Arc, Mutex};
+        use std::task::{Context, Poll, Wake, Waker};
+
+        type Waiting = Pin<Box<dyn Future<Output = &'static i32> + Send>>;
+
+        /// A waker that polls its future as soon as it is woken, which
+        /// registers the waker with the cell again.
+        struct Inline(Mutex<(Waiting, Option<i32>)>);
+
+        impl Inline {
+            fn poll(self: &Arc<Self>) {
+                let waker = Waker::from(Arc::clone(self));
+                let mut inner = self.0.lock().unwrap();
+                if let Poll::Ready(&value) = inner.0.as_mut().poll(&mut Context::from_waker(&waker)) {
+                    inner.1 = Some(value);
+                }
+            }
+        }
+
+        impl Wake for Inline {
+            fn wake(self: Arc<Self>) {
+                self.poll();
+            }
+        }
+
+        static CELL: sync::OnceCell<i32> = sync::OnceCell::new();
+        let inline = Arc::new(Inline(Mutex::new((Box::pin(CELL.wait_async()), None))));
+        inline.poll();
+        // The failed initializer wakes the task, which is still pending
+        // when it polls, inside of the wake.
+        assert_eq!(CELL.get_or_try_init(|| Err(())), Err(()));
+        assert_eq!(inline.0.lock().unwrap().1, None);
+        CELL.set(92).unwrap();
+        assert_eq!(inline.0.lock().unwrap().1, Some(92));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_panicking_waker_wakes_the_rest() {
+        use std::sync::Arc;
+        use std::task::{Wake, Waker};
+
+        struct Panicking;
+
+        impl Wake for Panicking {
+            fn wake(self: Arc<Self>) {
+                panic!("waker");
+            }
+        }
+
+        let cell = sync::OnceCell::new();
+        let (first_woken, first_waker) = FlagWaker::new();
+        let (last_woken, last_waker) = FlagWaker::new();
+        let mut first = Box::pin(cell.wait_async());
+        let mut panicking = Box::pin(cell.wait_async());
+        let mut last = Box::pin(cell.wait_async());
+        assert!(poll_once(&mut first, &first_waker).is_pending());
+        assert!(poll_once(&mut panicking, &Waker::from(Arc::new(Panicking))).is_pending());
+        assert!(poll_once(&mut last, &last_waker).is_pending());
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cell.set(92)));
+        assert!(res.is_err());
+        assert!(first_woken.take());
+        assert!(last_woken.take());
+        assert_eq!(cell.get(), Some(&92));
+        assert_eq!(poll_once(&mut first, &first_waker), std::task::Poll::Ready(&92));
+        assert_eq!(poll_once(&mut panicking, &first_waker), std::task::Poll::Ready(&92));
+        assert_eq!(poll_once(&mut last, &last_waker), std::task::Poll::Ready(&92));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_abandoned_waiters_are_freed() {
+        use std::time::Duration;
+
+        let cell = sync::OnceCell::new();
+        for _ in 0..10_000 {
+            assert_eq!(cell.wait_timeout(Duration::ZERO), None);
+        }
+        // SAFETY: nobody else uses the cell.
+        assert_eq!(unsafe { cell.queued_waiters() }, 0);
+
+        let (woken, waker) = FlagWaker::new();
+        for _ in 0..10_000 {
+            let mut waiting = Box::pin(cell.wait_async());
+            assert!(poll_once(&mut waiting, &waker).is_pending());
+        }
+        // SAFETY: nobody else uses the cell.
+        assert_eq!(unsafe { cell.queued_waiters() }, 0);
+
+        // Waiters that are still wanted stay queued.
+        let mut waiting = Box::pin(cell.wait_async());
+        assert!(poll_once(&mut waiting, &waker).is_pending());
+        for _ in 0..100 {
+            assert_eq!(cell.wait_timeout(Duration::ZERO), None);
+        }
+        cell.set(92).unwrap();
+        assert!(woken.take());
+        assert_eq!(poll_once(&mut waiting, &waker), std::task::Poll::Ready(&92));
+    }
+
+    #[test]
+    fn take_and_into_inner() {
+        let mut unsync_cell = unsync::OnceCell::from(String::from("a"));
+        assert_eq!(unsync_cell.take().as_deref(), Some("a"));
+        assert_eq!(unsync_cell.get(), None);
+        assert_eq!(unsync_cell.get_or_init(|| String::from("b")), "b");
+        assert_eq!(unsync_cell.into_inner().as_deref(), Some("b"));
+
+        let mut sync_cell = sync::OnceCell::new();
+        assert_eq!(sync_cell.take(), None);
+        sync_cell.get_or_init(|| String::from("a"));
+        sync_cell.get_mut().unwrap().push('!');
+        assert_eq!(sync_cell.take().as_deref(), Some("a!"));
+        assert_eq!(sync_cell.get(), None);
+        // The cell is reset to an empty state, so it can be filled again.
+        assert_eq!(sync_cell.set(String::from("b")), Ok(()));
+        assert_eq!(sync_cell.into_inner().as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn into_inner_drops_nothing_twice() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Dropper;
+        impl Drop for Dropper {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let cell = sync::OnceCell::new();
+        let _ = cell.set(Dropper);
+        let value = cell.into_inner();
+        assert_eq!(DROPS.load(Ordering::SeqCst), 0);
+        drop(value);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_once_cell_size() {
+        use std::mem::size_of;
+
+        // The value, the state word, the boxed poison and the policy.
+        assert_eq!(size_of::<sync::OnceCell<usize>>(), 5 * size_of::<usize>());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_poison_policy() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let mut cell = sync::OnceCell::with_policy(sync::PanicPolicy::Poison);
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| catch_unwind(AssertUnwindSafe(|| *cell.wait())));
+            // SAFETY: the cell is only poisoned below.
+            wait_until_queued(1, || unsafe { cell.queued_waiters() });
+
+            let res = catch_unwind(AssertUnwindSafe(|| cell.get_or_init(|| panic!("no config"))));
+            assert!(res.is_err());
+            // The waiter is released instead of hanging forever.
+            assert!(waiter.join().unwrap().is_err());
+        });
+
+        assert!(cell.is_poisoned());
+        assert_eq!(cell.poison_error().map(sync::PoisonError::message), Some("no config"));
+        assert_eq!(format!("{:?}", cell), "OnceCell(Poisoned)");
+        assert_eq!(cell.set(1), Err(1));
+
+        let res = catch_unwind(AssertUnwindSafe(|| cell.get_or_init(|| 1)));
+        let msg = res.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(*msg, "OnceCell instance has previously been poisoned: no config");
+
+        cell.clear_poison();
+        assert!(!cell.is_poisoned());
+        assert_eq!(cell.get_or_init(|| 92), &92);
+        assert_eq!(cell.take(), Some(92));
+        assert_eq!(cell.policy(), sync::PanicPolicy::Poison);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_lazy_policies() {
+        use std::panic::catch_unwind;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+        fn flaky() -> usize {
+            match ATTEMPTS.fetch_add(1, Ordering::SeqCst) {
+                0 => panic!("transient"),
+                n => n,
+            }
+        }
+
+        static RETRY: sync::Lazy<usize> = sync::Lazy::with_policy(flaky, sync::PanicPolicy::Retry);
+        assert!(catch_unwind(|| *RETRY).is_err());
+        assert!(!sync::Lazy::is_poisoned(&RETRY));
+        assert_eq!(*RETRY, 1);
+
+        static POISON: sync::Lazy<usize> = sync::Lazy::new(|| panic!("permanent"));
+        assert!(catch_unwind(|| *POISON).is_err());
+        assert!(sync::Lazy::is_poisoned(&POISON));
+        assert_eq!(sync::Lazy::poison_error(&POISON).unwrap().message(), "permanent");
+
+        let mut cleared: sync::Lazy<usize> = sync::Lazy::new(|| panic!("permanent"));
+        assert!(catch_unwind(std::panic::AssertUnwindSafe(|| *cleared)).is_err());
+        sync::Lazy::clear_poison(&mut cleared, || 92);
+        assert!(!sync::Lazy::is_poisoned(&cleared));
+        assert_eq!(sync::Lazy::poison_error(&cleared), None);
+        assert_eq!(*cleared, 92);
+        assert_eq!(sync::Lazy::into_value(cleared).ok(), Some(92));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_abort_policy() {
+        // Aborting takes the whole process down, so run the panicking
+        // initializer in a child process running just this test.
+        if std::env::var_os("OUR_ONCE_CELL_ABORT_CHILD").is_some() {
+            let cell = sync::OnceCell::with_policy(sync::PanicPolicy::Abort);
+            let _ = std::panic::catch_unwind(|| cell.get_or_init(|| -> u32 { panic!("fatal") }));
+            // Unreachable if the policy is honoured.
+            std::process::exit(0);
+        }
+
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "tests::sync_abort_policy", "--nocapture"])
+            .env("OUR_ONCE_CELL_ABORT_CHILD", "1")
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!status.success());
+    }
+
+    /// Counts its drops in the counter it borrows.
+    #[cfg(feature = "alloc")]
+    struct Counted<'a>(&'a std::sync::atomic::AtomicUsize);
+
+    #[cfg(feature = "alloc")]
+    impl Drop for Counted<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn race_once_box_drops_loser() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let drops = AtomicUsize::new(0);
+        let cell = race::OnceBox::new();
+        assert!(cell.get().is_none());
+        assert!(cell.set(Box::new(Counted(&drops))).is_ok());
+
+        let loser = cell.set(Box::new(Counted(&drops))).unwrap_err();
+        drop(loser);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+
+        cell.get_or_init(|| Box::new(Counted(&drops)));
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        drop(cell);
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn race_once_box_from_many_threads() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CELL: race::OnceBox<String> = race::OnceBox::new();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    CELL.get_or_init(|| {
+                        CALLS.fetch_add(1, Ordering::SeqCst);
+                        Box::new(i.to_string())
+                    })
+                    .clone()
+                })
+            })
+            .collect();
+        let values: Vec<String> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert!(CALLS.load(Ordering::SeqCst) >= 1);
+        assert!(values.iter().all(|v| v == CELL.get().unwrap()));
+    }
+
+    #[test]
+    fn race_once_non_zero_usize_and_bool() {
+        use std::num::NonZeroUsize;
+
+        let cell = race::OnceNonZeroUsize::new();
+        assert_eq!(cell.get(), None);
+        let res: Result<_, ()> = cell.get_or_try_init(|| Err(()));
+        assert_eq!(res, Err(()));
+        assert_eq!(cell.get(), None);
+
+        let seven = NonZeroUsize::new(7).unwrap();
+        assert_eq!(cell.get_or_init(|| seven), seven);
+        assert_eq!(cell.get_or_init(|| unreachable!()), seven);
+
+        let flag = race::OnceBool::new();
+        assert_eq!(flag.get(), None);
+        assert!(!flag.get_or_init(|| false));
+        assert_eq!(flag.set(true), Err(true));
+        assert_eq!(flag.get(), Some(false));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn model_set_races_set() {
+        use std::sync::Arc;
+
+        let executions = model::check_unbounded(|| {
+            let cell = Arc::new(sync::OnceCell::new());
+            let other = {
+                let cell = Arc::clone(&cell);
+                model::spawn(move || cell.set(1).is_ok())
+            };
+            let mine = cell.set(2).is_ok();
+            let theirs = other.join();
+            assert!(mine ^ theirs, "exactly one `set` must win");
+            assert_eq!(cell.get(), Some(if mine { &2 } else { &1 }));
+        });
+        assert!(executions > 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn model_get_or_init_runs_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        model::check_unbounded(|| {
+            let cell = Arc::new(sync::OnceCell::new());
+            let calls = Arc::new(AtomicUsize::new(0));
+            let init = |i| {
+                let cell = Arc::clone(&cell);
+                let calls = Arc::clone(&calls);
+                move || {
+                    *cell.get_or_init(|| {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        i
+                    })
+                }
+            };
+            let other = model::spawn(init(1));
+            let mine = init(2)();
+            assert_eq!(mine, other.join());
+            assert_eq!(calls.load(Ordering::SeqCst), 1);
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn model_get_sees_whole_value() {
+        use std::sync::Arc;
+
+        model::check_unbounded(|| {
+            let cell = Arc::new(sync::OnceCell::new());
+            let reader = {
+                let cell = Arc::clone(&cell);
+                model::spawn(move || cell.get().cloned())
+            };
+            cell.set(String::from("hello")).unwrap();
+            match reader.join() {
+                None => {}
+                Some(value) => assert_eq!(value, "hello"),
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn model_wait_sees_value() {
+        use std::sync::Arc;
+
+        // Three threads have too many interleavings to explore them all.
+        model::check(|| {
+            let cell = Arc::new(sync::OnceCell::new());
+            let waiter = {
+                let cell = Arc::clone(&cell);
+                model::spawn(move || *cell.wait())
+            };
+            let failed = {
+                let cell = Arc::clone(&cell);
+                model::spawn(move || cell.get_or_try_init(|| Err(())).is_err())
+            };
+            let value = *cell.get_or_init(|| 2);
+            // The failed initializer either ran first and handed over, or
+            // found the cell initialized.
+            failed.join();
+            assert_eq!(waiter.join(), value);
+            assert_eq!(value, 2);
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn model_wait_timeout() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let timed_out = AtomicBool::new(false);
+        let woken = AtomicBool::new(false);
+        model::check_unbounded(|| {
+            let cell = Arc::new(sync::OnceCell::new());
+            let waiter = {
+                let cell = Arc::clone(&cell);
+                model::spawn(move || cell.wait_timeout(Duration::from_secs(1)).copied())
+            };
+            cell.set(92).unwrap();
+            match waiter.join() {
+                None => timed_out.store(true, Ordering::Relaxed),
+                Some(value) => {
+                    assert_eq!(value, 92);
+                    woken.store(true, Ordering::Relaxed);
+                }
+            }
+        });
+        assert!(timed_out.load(Ordering::Relaxed));
+        assert!(woken.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn model_abandoned_waiters() {
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        // Three threads have too many interleavings to explore them all.
+        model::check(|| {
+            let cell = Arc::new(sync::OnceCell::new());
+            let timed = {
+                let cell = Arc::clone(&cell);
+                model::spawn(move || cell.wait_timeout(Duration::from_secs(1)).copied())
+            };
+            let blocked = {
+                let cell = Arc::clone(&cell);
+                model::spawn(move || *cell.wait())
+            };
+            cell.set(92).unwrap();
+            assert!(matches!(timed.join(), None | Some(92)));
+            assert_eq!(blocked.join(), 92);
+            // Every waiter was either woken up or freed.
+            // SAFETY: every other thread has been joined.
+            assert_eq!(unsafe { cell.queued_waiters() }, 0);
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn model_dropped_wait_async() {
+        use std::sync::Arc;
+
+        // Three threads have too many interleavings to explore them all.
+        model::check(|| {
+            let cell = Arc::new(sync::OnceCell::new());
+            let task = {
+                let cell = Arc::clone(&cell);
+                model::spawn(move || {
+                    let (_, waker) = FlagWaker::new();
+                    let mut waiting = Box::pin(cell.wait_async());
+                    // Polling again re-registers the waker, under the lock
+                    // that the transition takes to wake the task.
+                    for _ in 0..2 {
+                        if let std::task::Poll::Ready(&value) = poll_once(&mut waiting, &waker) {
+                            return Some(value);
+                        }
+                    }
+                    None
+                })
+            };
+            let blocked = {
+                let cell = Arc::clone(&cell);
+                model::spawn(move || *cell.wait())
+            };
+            cell.set(92).unwrap();
+            assert!(matches!(task.join(), None | Some(92)));
+            assert_eq!(blocked.join(), 92);
+            // SAFETY: every other thread has been joined.
+            assert_eq!(unsafe { cell.queued_waiters() }, 0);
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn model_single_flight() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let executions = model::check_unbounded(|| {
+            let flight = Arc::new(sync::SingleFlight::new());
+            let calls = Arc::new(AtomicUsize::new(0));
+            let run = || {
+                let flight = Arc::clone(&flight);
+                let calls = Arc::clone(&calls);
+                move || flight.run("key", || calls.fetch_add(1, Ordering::SeqCst))
+            };
+            let other = model::spawn(run());
+            let mine = run()();
+            let other = other.join();
+            // Either the calls overlapped and shared a result, or they ran
+            // one after the other.
+            match calls.load(Ordering::SeqCst) {
+                1 => assert_eq!((mine, other), (0, 0)),
+                2 => assert_ne!(mine, other),
+                n => panic!("{} calls", n),
+            }
+            assert_eq!(flight.in_flight(), 0);
+        });
+        assert!(executions > 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    #[should_panic(expected = "both threads won")]
+    fn model_finds_races() {
+        use std::sync::atomic::Ordering::SeqCst;
+        use std::sync::Arc;
+
+        // A check-then-act race, which the scheduler has to find.
+        model::check_unbounded(|| {
+            let flag = Arc::new(shim::AtomicBool::new(false));
+            let other = {
+                let flag = Arc::clone(&flag);
+                model::spawn(move || {
+                    let won = !flag.load(SeqCst);
+                    flag.store(true, SeqCst);
+                    won
+                })
+            };
+            let won = !flag.load(SeqCst);
+            flag.store(true, SeqCst);
+            assert!(!(won && other.join()), "both threads won");
+        });
+    }
+
+    #[test]
+    fn auto_traits() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+
+        assert_send::<unsync::OnceCell<String>>();
+        assert_send::<sync::OnceCell<String>>();
+        assert_sync::<sync::OnceCell<String>>();
+        assert_send::<sync::OnceCell<std::cell::Cell<i32>>>();
+        assert_sync::<sync::Lazy<String>>();
+        #[cfg(feature = "std")]
+        assert_send::<r#async::OnceCell<String>>();
+        #[cfg(feature = "std")]
+        assert_sync::<r#async::OnceCell<String>>();
+        #[cfg(feature = "alloc")]
+        assert_send::<race::OnceBox<String>>();
+        #[cfg(feature = "alloc")]
+        assert_sync::<race::OnceBox<String>>();
+        assert_sync::<race::OnceBool>();
+        #[cfg(feature = "std")]
+        assert_sync::<sync::OnceMap<u32, String>>();
+        #[cfg(feature = "std")]
+        assert_send::<sync::OnceMap<u32, std::cell::Cell<i32>>>();
+        #[cfg(feature = "alloc")]
+        assert_sync::<sync::OnceVec<String>>();
+        #[cfg(feature = "alloc")]
+        assert_send::<sync::OnceVec<std::cell::Cell<i32>>>();
+    }
+
+    #[test]
+    fn trait_impls_agree() {
+        let unsync_cell = unsync::OnceCell::from(92);
+        let sync_cell = sync::OnceCell::from(92);
+
+        assert_eq!(format!("{:?}", unsync_cell), "OnceCell(92)");
+        assert_eq!(format!("{:?}", sync_cell), "OnceCell(92)");
+        assert_eq!(unsync_cell.clone(), unsync_cell);
+        assert_eq!(sync_cell.clone(), sync_cell);
+
+        let empty: sync::OnceCell<i32> = Default::default();
+        assert_eq!(format!("{:?}", empty), "OnceCell(Uninit)");
+        assert_ne!(empty, sync_cell);
+        assert_eq!(unsync::OnceCell::<i32>::default(), unsync::OnceCell::new());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_once_map_runs_once_per_key() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let map = sync::OnceMap::new();
+        let calls = [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for (key, count) in calls.iter().enumerate() {
+                        let value = map.get_or_init(key, || {
+                            count.fetch_add(1, Ordering::Relaxed);
+                            std::thread::yield_now();
+                            key * 10
+                        });
+                        assert_eq!(*value, key * 10);
+                    }
+                });
+            }
+        });
+        for (key, count) in calls.iter().enumerate() {
+            assert_eq!(count.load(Ordering::Relaxed), 1);
+            assert_eq!(map.get(&key), Some(&(key * 10)));
+        }
+        assert_eq!(map.get(&3), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_once_map_keys_do_not_block_each_other() {
+        // The initializer of "a" only finishes once "b" has been initialized
+        // from another thread, which deadlocks if the map stays locked.
+        let map = sync::OnceMap::new();
+        let b_done = sync::OnceCell::new();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                map.get_or_init("a", || {
+                    b_done.wait();
+                    1
+                })
+            });
+            s.spawn(|| {
+                while map.get("a").is_none() {
+                    let _ = map.get_or_init("b", || 2);
+                    let _ = b_done.set(());
+                    std::thread::yield_now();
+                }
+            });
+        });
+        assert_eq!(format!("{:?}", map.get("a").zip(map.get("b"))), "Some((1, 2))");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_once_map_retries_and_removes() {
+        let mut map = sync::OnceMap::new();
+        assert_eq!(map.get_or_try_init(1, || Err(())), Err(()));
+        assert_eq!(map.get(&1), None);
+        assert_eq!(format!("{:?}", map), "{}");
+        assert_eq!(map.get_or_try_init(1, || Ok::<_, ()>(92)), Ok(&92));
+        assert_eq!(format!("{:?}", map), "{1: 92}");
+
+        assert_eq!(map.remove(&1), Some(92));
+        assert_eq!(map.remove(&1), None);
+        assert_eq!(map.get_or_init(1, || 62), &62);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn once_vec_buckets() {
+        use crate::buckets::{bucket_len, locate, BUCKETS};
+
+        assert_eq!(locate(0), Some((0, 0)));
+        assert_eq!(locate(7), Some((0, 7)));
+        assert_eq!(locate(8), Some((1, 0)));
+        assert_eq!(locate(23), Some((1, 15)));
+        assert_eq!(locate(24), Some((2, 0)));
+        let last = usize::MAX - bucket_len(0);
+        assert_eq!(locate(last), Some((BUCKETS - 1, bucket_len(BUCKETS - 1) - 1)));
+        assert_eq!(locate(last + 1), None);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn unsync_once_vec() {
+        let vec = unsync::OnceVec::new();
+        assert!(vec.is_empty());
+        assert_eq!(vec.get(0), None);
+        assert_eq!(vec.get(usize::MAX), None);
+
+        let (index, first) = vec.push(String::from("first"));
+        assert_eq!(index, 0);
+        for i in 1..100 {
+            assert_eq!(vec.push(i.to_string()).0, i);
+        }
+        assert_eq!(first, "first");
+        assert_eq!(vec.len(), 100);
+        assert_eq!(vec.get(42).map(String::as_str), Some("42"));
+        assert_eq!(vec.get(100), None);
+
+        let mut seen = 0;
+        for (i, value) in vec.iter().enumerate() {
+            if i == 0 {
+                // Elements pushed while iterating are visited too.
+                vec.push(String::from("last"));
+            }
+            seen += 1;
+            assert_eq!(vec.get(i), Some(value));
+        }
+        assert_eq!(seen, 101);
+        assert_eq!(format!("{:?}", unsync::OnceVec::<i32>::new()), "[]");
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn sync_once_vec_from_many_threads() {
+        const THREADS: usize = 8;
+        const PUSHES: usize = 500;
+
+        let vec = sync::OnceVec::new();
+        std::thread::scope(|s| {
+            for t in 0..THREADS {
+                let vec = &vec;
+                s.spawn(move || {
+                    for i in 0..PUSHES {
+                        let (index, value) = vec.push((t, i));
+                        assert_eq!(vec.get(index), Some(value));
+                    }
+                });
+            }
+            // Reads never block, and only ever see whole elements.
+            s.spawn(|| {
+                while vec.len() < THREADS * PUSHES {
+                    for &(t, i) in &vec {
+                        assert!(t < THREADS && i < PUSHES);
+                    }
+                }
+            });
+        });
+
+        assert_eq!(vec.len(), THREADS * PUSHES);
+        let mut values: Vec<_> = vec.iter().copied().collect();
+        values.sort_unstable();
+        let expected: Vec<_> = (0..THREADS).flat_map(|t| (0..PUSHES).map(move |i| (t, i))).collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn sync_once_vec_drops_values_and_stays_small() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // One pointer per bucket, and the length.
+        let words = crate::buckets::BUCKETS + 1;
+        assert_eq!(std::mem::size_of::<sync::OnceVec<u8>>(), words * std::mem::size_of::<usize>());
+
+        let drops = AtomicUsize::new(0);
+        let vec = sync::OnceVec::new();
+        for _ in 0..100 {
+            vec.push(Counted(&drops));
+        }
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+        drop(vec);
+        assert_eq!(drops.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_single_flight_coalesces() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Barrier;
+        use std::time::Duration;
+
+        let flight = sync::SingleFlight::new();
+        let calls = AtomicUsize::new(0);
+        let barrier = Barrier::new(8);
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    barrier.wait();
+                    let value = flight.run("key", || {
+                        std::thread::sleep(Duration::from_millis(50));
+                        calls.fetch_add(1, Ordering::SeqCst) + 92
+                    });
+                    assert_eq!(value, 92);
+                });
+            }
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(flight.in_flight(), 0);
+
+        // Nothing is retained: the next call computes again.
+        assert_eq!(flight.run("key", || 62), 62);
+        assert_eq!(format!("{:?}", flight), "SingleFlight { in_flight: 0 }");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_single_flight_shares_errors_and_forgets_panics() {
+        let flight = sync::SingleFlight::new();
+        let res: Result<i32, String> = flight.run(1, || Err(String::from("boom")));
+        assert_eq!(res, Err(String::from("boom")));
+        assert_eq!(flight.run(1, || Ok(92)), Ok(92));
+
+        let res = std::panic::catch_unwind(|| flight.run(2, || panic!("lost")));
+        assert!(res.is_err());
+        assert_eq!(flight.in_flight(), 0);
+        assert_eq!(flight.run(2, || Ok(62)), Ok(62));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_promise_fulfilled() {
+        let (promise, completion) = sync::promise();
+        assert_eq!(completion.try_get(), None);
+        assert_eq!(format!("{:?}", completion), "Completion(Pending)");
+        std::thread::scope(|s| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    let completion = completion.clone();
+                    s.spawn(move || completion.wait().cloned())
+                })
+                .collect();
+            let task = s.spawn({
+                let completion = completion.clone();
+                move || block_on(completion)
+            });
+            // Four blocked readers and a pending task.
+            // SAFETY: the promise is only fulfilled below.
+            wait_until_queued(5, || unsafe { completion.queued_readers() });
+            promise.fulfil(String::from("done"));
+            for reader in readers {
+                assert_eq!(reader.join().unwrap().as_deref(), Ok("done"));
+            }
+            assert_eq!(task.join().unwrap().as_deref(), Ok("done"));
+        });
+        assert_eq!(completion.try_get().map(|res| res.cloned()), Some(Ok(String::from("done"))));
+        assert_eq!(format!("{:?}", completion), r#"Completion(Ok("done"))"#);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_promise_broken() {
+        let (promise, completion) = sync::promise::<i32>();
+        std::thread::scope(|s| {
+            let reader = s.spawn(|| completion.wait().copied());
+            let task = s.spawn(|| block_on(completion.clone()));
+            // SAFETY: the promise is only broken below.
+            wait_until_queued(2, || unsafe { completion.queued_readers() });
+            drop(promise);
+            assert_eq!(reader.join().unwrap(), Err(sync::Broken));
+            assert_eq!(task.join().unwrap(), Err(sync::Broken));
+        });
+        assert_eq!(completion.try_get(), Some(Err(sync::Broken)));
+        assert_eq!(sync::Broken.to_string(), "promise dropped without being fulfilled");
+
+        // A promise dropped while unwinding breaks as well.
+        let (promise, completion) = sync::promise::<i32>();
+        let res = std::panic::catch_unwind(move || {
+            let _promise = promise;
+            panic!("worker died");
+        });
+        assert!(res.is_err());
+        assert_eq!(completion.wait(), Err(sync::Broken));
+    }
+
+    /// A clock that only moves when told to.
+    #[cfg(feature = "std")]
+    struct ManualClock(std::sync::Mutex<std::time::Instant>);
+
+    #[cfg(feature = "std")]
+    impl ManualClock {
+        fn new() -> ManualClock {
+            ManualClock(std::sync::Mutex::new(std::time::Instant::now()))
+        }
+
+        fn advance(&self, by: std::time::Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl sync::Clock for ManualClock {
+        fn now(&self) -> std::time::Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_cached_expires() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let clock = ManualClock::new();
+        let loads = AtomicUsize::new(0);
+        let cached = sync::Cached::with_clock(
+            Duration::from_secs(10),
+            || loads.fetch_add(1, Ordering::SeqCst),
+            &clock,
+        );
+
+        let first = cached.get();
+        assert_eq!(*first, 0);
+        clock.advance(Duration::from_secs(9));
+        assert!(Arc::ptr_eq(&cached.get(), &first));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(*cached.get(), 1);
+        assert_eq!(*cached.get(), 1);
+
+        cached.invalidate();
+        assert_eq!(*cached.get(), 2);
+        assert_eq!(*first, 0);
+        assert_eq!(loads.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            format!("{:?}", cached),
+            r#"Cached { value: Some(2), ttl: 10s, init: ".." }"#
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_cached_serves_stale_while_refreshing() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let clock = ManualClock::new();
+        let loads = AtomicUsize::new(0);
+        let entered = sync::OnceCell::new();
+        let release = sync::OnceCell::new();
+        let cached = sync::Cached::with_clock(
+            Duration::from_secs(10),
+            || {
+                let n = loads.fetch_add(1, Ordering::SeqCst);
+                if n == 1 {
+                    entered.set(()).unwrap();
+                    release.wait();
+                }
+                n
+            },
+            &clock,
+        );
+
+        // The first value is waited for.
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| assert_eq!(*cached.get(), 0));
+            }
+        });
+
+        clock.advance(Duration::from_secs(10));
+        std::thread::scope(|s| {
+            let refresher = s.spawn(|| *cached.get());
+            entered.wait();
+            // The refresh is stuck, yet readers get the stale value, and do
+            // not start a refresh of their own.
+            for _ in 0..4 {
+                assert_eq!(*cached.get(), 0);
+            }
+            release.set(()).unwrap();
+            assert_eq!(refresher.join().unwrap(), 1);
+        });
+        assert_eq!(*cached.get(), 1);
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn sync_cached_refresh_panics() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let loads = AtomicUsize::new(0);
+        let cached = sync::Cached::new(Duration::from_secs(3600), || {
+            let n = loads.fetch_add(1, Ordering::SeqCst);
+            assert_ne!(n, 1, "refresh failed");
+            n
+        });
+        assert_eq!(*cached.get(), 0);
+
+        cached.invalidate();
+        let res = std::panic::catch_unwind(|| cached.get());
+        assert!(res.is_err());
+        // The next access refreshes again.
+        assert_eq!(*cached.get(), 2);
+    }
+
+    /// Patterns that would be undefined behavior if a write to a cell ever
+    /// went through a `&mut` aliasing a reference handed out earlier,
+    /// including from within the cell's own initializer. They pass either
+    /// way in a regular test run, but Miri's Stacked and Tree Borrows checks
+    /// would flag them: run with `cargo miri test aliasing`.
+    mod aliasing {
+        use super::*;
+        #[cfg(not(feature = "std"))]
+        use std::{vec, vec::Vec};
+
+        #[test]
+        fn unsync_get_survives_set() {
+            let cell = unsync::OnceCell::new();
+            assert!(cell.get().is_none());
+            assert_eq!(cell.set(vec![1, 2, 3]), Ok(()));
+            let first = &cell.get().unwrap()[0];
+            assert_eq!(cell.set(vec![4]), Err(vec![4]));
+            assert_eq!(cell.get_or_init(|| unreachable!()).len(), 3);
+            assert_eq!(*first, 1);
+        }
+
+        #[test]
+        fn unsync_empty_get_then_set() {
+            // The `&Option<T>` behind a `None` from `get` must not be live
+            // when `set` writes.
+            let cell = unsync::OnceCell::new();
+            let empty = cell.get();
+            assert_eq!(cell.set(92), Ok(()));
+            assert!(empty.is_none());
+            assert_eq!(cell.get(), Some(&92));
+        }
+
+        #[test]
+        fn unsync_reentrant_set_while_borrowed() {
+            // The initializer fills the cell itself and keeps a `&T` to that
+            // value while `set` and `get` are entered again, and while the
+            // outer `get_or_init` tries to store its own value.
+            let cell = unsync::OnceCell::new();
+            let held = core::cell::Cell::new(None);
+            let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                cell.get_or_init(|| {
+                    assert_eq!(cell.set(vec![92]), Ok(()));
+                    held.set(cell.get());
+                    assert_eq!(cell.set(vec![62]), Err(vec![62]));
+                    assert_eq!(cell.get().map(|v| v[0]), Some(92));
+                    vec![1]
+                })
+                .len()
+            }));
+            assert!(res.is_err());
+            assert_eq!(held.get().unwrap()[0], 92);
+            assert_eq!(cell.get(), Some(&vec![92]));
+        }
+
+        #[test]
+        fn unsync_value_borrows_other_cell() {
+            let a = unsync::OnceCell::new();
+            let b = unsync::OnceCell::new();
+            let inner = a.get_or_init(|| 92);
+            let outer: &&i32 = b.get_or_init(|| a.get().unwrap());
+            assert_eq!(a.set(62), Err(62));
+            assert_eq!((*inner, **outer), (92, 92));
+        }
+
+        #[test]
+        fn unsync_get_mut_after_get() {
+            let mut cell = unsync::OnceCell::new();
+            let _ = cell.get_or_init(|| 1);
+            *cell.get_mut().unwrap() += 1;
+            let shared = cell.get().unwrap();
+            assert_eq!(*shared, 2);
+            assert_eq!(cell.take(), Some(2));
+            assert_eq!(cell.set(3), Ok(()));
+            assert_eq!(cell.get(), Some(&3));
+        }
+
+        #[test]
+        fn unsync_lazy_held_across_force() {
+            let lazy = unsync::Lazy::new(|| vec![92]);
+            let held: &Vec<i32> = &lazy;
+            assert_eq!(unsync::Lazy::force(&lazy), &vec![92]);
+            assert_eq!(unsync::Lazy::get(&lazy).map(Vec::len), Some(1));
+            assert_eq!(held[0], 92);
+        }
+
+        #[test]
+        fn sync_get_survives_concurrent_set() {
+            let cell = sync::OnceCell::new();
+            assert_eq!(cell.set(vec![1, 2, 3]), Ok(()));
+            let first = &cell.get().unwrap()[0];
+            std::thread::scope(|s| {
+                for i in 0..4 {
+                    let cell = &cell;
+                    s.spawn(move || {
+                        assert_eq!(cell.set(vec![i]), Err(vec![i]));
+                        assert_eq!(cell.get_or_init(|| unreachable!())[2], 3);
+                    });
+                }
+                assert_eq!(*first, 1);
+            });
+            assert_eq!(*first, 1);
+        }
+
+        #[test]
+        fn sync_value_borrows_other_cell() {
+            let a = sync::OnceCell::new();
+            let b = sync::OnceCell::new();
+            let inner = a.get_or_init(|| 92);
+            std::thread::scope(|s| {
+                s.spawn(|| b.get_or_init(|| a.get().unwrap()));
+                s.spawn(|| a.set(62));
+            });
+            assert_eq!((*inner, **b.get().unwrap()), (92, 92));
+        }
+
+        #[test]
+        #[cfg(feature = "alloc")]
+        fn race_once_box_get_survives_lost_set() {
+            let cell = race::OnceBox::new();
+            let held = cell.get_or_init(|| Box::new(vec![92]));
+            assert!(cell.set(Box::new(vec![62])).is_err());
+            assert_eq!(cell.get_or_init(|| unreachable!()), &vec![92]);
+            assert_eq!(held[0], 92);
+        }
+
+        #[test]
+        #[cfg(feature = "alloc")]
+        fn once_vec_values_survive_growth() {
+            let local = unsync::OnceVec::new();
+            let shared = sync::OnceVec::new();
+            let (_, first_local) = local.push(vec![92]);
+            let (_, first_shared) = shared.push(vec![92]);
+            for i in 1..256 {
+                local.push(vec![i]);
+                shared.push(vec![i]);
+            }
+            assert_eq!(local.get(255), Some(&vec![255]));
+            assert_eq!(shared.get(255), Some(&vec![255]));
+            assert_eq!((first_local[0], first_shared[0]), (92, 92));
+        }
+
+        #[test]
+        #[cfg(feature = "std")]
+        fn sync_once_map_value_survives_growth() {
+            let map = sync::OnceMap::new();
+            let first = map.get_or_init(0, || vec![92]);
+            for key in 1..256 {
+                map.get_or_init(key, || vec![key]);
+            }
+            assert_eq!(map.get(&0).map(|v| v[0]), Some(92));
+            assert_eq!(first[0], 92);
+        }
     }
 }
@@ -0,0 +1,688 @@
+//! A deterministic scheduler for checking the `std` backend of
+//! `sync::OnceCell`, in the spirit of `loom`.
+//!
+//! [`check`] runs a test body over and over, once for every way its threads
+//! can interleave. Model threads are real OS threads, but only one of them
+//! runs at a time: the others wait for the scheduler to pass them the baton.
+//! Every operation on the [`AtomicPtr`] and [`AtomicBool`] wrappers, every
+//! [`Mutex`] lock and unlock, and every park, unpark, spawn and join, is a
+//! point where the scheduler may switch threads. The choices made at those
+//! points are explored depth first, so each execution differs from the
+//! previous one in its last choice that still had alternatives.
+//!
+//! Time is virtual. A thread in a timed park may time out at any point
+//! where the scheduler may switch threads, which moves the clock returned
+//! by [`now`] forward to its deadline.
+//!
+//! The search is not exhaustive in general:
+//!
+//! * Threads run one at a time, so every execution is sequentially
+//!   consistent. Reorderings allowed by weaker memory orderings are never
+//!   explored.
+//! * [`check`] bounds the number of preemptions, that is switches away from
+//!   a thread that could have kept running, by [`DEFAULT_PREEMPTION_BOUND`].
+//!   Most concurrency bugs need very few of them, but a bug that needs more
+//!   is missed. [`check_unbounded`] explores every interleaving instead,
+//!   which is only feasible for two threads doing a handful of operations:
+//!   the number of executions grows exponentially with the number of
+//!   threads.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
+use std::ops::{Deref, DerefMut};
+use std::sync::{self, Arc, Condvar, LockResult, PoisonError, TryLockError};
+use std::thread as os;
+use std::time::{Duration, Instant};
+
+/// The maximum number of preemptions in one execution, unless a
+/// [`Builder`] says otherwise.
+pub(crate) const DEFAULT_PREEMPTION_BOUND: usize = 3;
+
+thread_local! {
+    /// The execution and id of the current model thread, if any.
+    static CURRENT: RefCell<Option<(Arc<Execution>, usize)>> = const { RefCell::new(None) };
+}
+
+fn current() -> Option<(Arc<Execution>, usize)> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Lets the scheduler switch threads before an operation, if we are on a
+/// model thread.
+fn yield_now() {
+    if let Some((exec, me)) = current() {
+        exec.yield_now(me);
+    }
+}
+
+/// Returns the current time: the virtual time of the execution on a model
+/// thread, and the real time elsewhere.
+pub(crate) fn now() -> Instant {
+    match current() {
+        Some((exec, _)) => exec.start + exec.lock().elapsed,
+        None => Instant::now(),
+    }
+}
+
+/// Explores every interleaving of the model threads spawned by `body`, up
+/// to [`DEFAULT_PREEMPTION_BOUND`] preemptions, and returns the number of
+/// executions.
+///
+/// Panics if a model thread panics or the threads deadlock in any
+/// execution, with the schedule that led there.
+pub(crate) fn check<F: Fn() + Sync>(body: F) -> usize {
+    Builder::new().check(body)
+}
+
+/// Explores every interleaving of the model threads spawned by `body`,
+/// without bounding the number of preemptions, and returns the number of
+/// executions.
+///
+/// Panics like [`check`].
+pub(crate) fn check_unbounded<F: Fn() + Sync>(body: F) -> usize {
+    Builder {
+        preemption_bound: None,
+    }
+    .check(body)
+}
+
+/// The settings of a model check.
+pub(crate) struct Builder {
+    /// The maximum number of preemptions in one execution, or `None` to
+    /// explore every interleaving.
+    pub(crate) preemption_bound: Option<usize>,
+}
+
+impl Builder {
+    pub(crate) fn new() -> Builder {
+        Builder {
+            preemption_bound: Some(DEFAULT_PREEMPTION_BOUND),
+        }
+    }
+
+    /// Like [`check`], with these settings.
+    pub(crate) fn check<F: Fn() + Sync>(&self, body: F) -> usize {
+        let mut path = Path::default();
+        let mut executions = 0;
+        loop {
+            executions += 1;
+            let exec = Arc::new(Execution::new(path, self.preemption_bound));
+            os::scope(|scope| {
+                let exec = Arc::clone(&exec);
+                let body = &body;
+                scope.spawn(move || exec.run(0, body));
+            });
+            // Threads spawned inside the execution may still be running.
+            while let Some(handle) = exec
+                .handles
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .pop()
+            {
+                let _ = handle.join();
+            }
+
+            let sched = exec.lock();
+            if let Some(failure) = &sched.failure {
+                panic!(
+                    "execution {} failed: {}\nschedule: {:?}",
+                    executions, failure, sched.trace
+                );
+            }
+            path = sched.path.clone();
+            if !path.advance() {
+                return executions;
+            }
+        }
+    }
+}
+
+/// Spawns a model thread. Must be called from a model thread.
+pub(crate) fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (exec, me) = current().expect("model::spawn called outside of model::check");
+    let result = Arc::new(sync::Mutex::new(None));
+    let id = {
+        let mut sched = exec.lock();
+        sched.threads.push(Run::Runnable);
+        sched.tokens.push(false);
+        sched.threads.len() - 1
+    };
+    let handle = {
+        let exec = Arc::clone(&exec);
+        let result = Arc::clone(&result);
+        os::spawn(move || {
+            exec.run(id, || {
+                let value = f();
+                *result.lock().unwrap_or_else(PoisonError::into_inner) = Some(value);
+            })
+        })
+    };
+    exec.handles
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(handle);
+    exec.yield_now(me);
+    JoinHandle { id, result }
+}
+
+/// A handle to a model thread.
+pub(crate) struct JoinHandle<T> {
+    id: usize,
+    result: Arc<sync::Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Blocks the current model thread until the thread has finished, and
+    /// returns its result.
+    pub(crate) fn join(self) -> T {
+        let (exec, me) = current().expect("JoinHandle::join called outside of model::check");
+        exec.yield_now(me);
+        let mut sched = exec.lock();
+        if sched.threads[self.id] != Run::Finished {
+            sched.threads[me] = Run::Joining(self.id);
+            exec.switch(sched, me);
+        }
+        let value = self
+            .result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        // A thread that panicked has aborted the execution, and we only get
+        // here while unwinding out of it.
+        value.unwrap_or_else(|| panic::resume_unwind(Box::new(Aborted)))
+    }
+}
+
+/// What a model thread is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Run {
+    Runnable,
+    Parked,
+    /// Parked until the given virtual time, or until unparked.
+    ParkedUntil(Duration),
+    Joining(usize),
+    /// Waiting for the mutex at the given address to be unlocked.
+    Locking(usize),
+    Finished,
+}
+
+/// The payload of the panics that unwind model threads out of an aborted
+/// execution.
+struct Aborted;
+
+/// The choices made at every scheduling point that had more than one
+/// option, as `(chosen, options)`.
+#[derive(Clone, Debug, Default)]
+struct Path {
+    branches: Vec<(usize, usize)>,
+    pos: usize,
+}
+
+impl Path {
+    fn choose(&mut self, options: usize) -> usize {
+        if options == 1 {
+            return 0;
+        }
+        if let Some(&(chosen, expected)) = self.branches.get(self.pos) {
+            assert_eq!(expected, options, "the execution is not deterministic");
+            self.pos += 1;
+            return chosen;
+        }
+        self.branches.push((0, options));
+        self.pos += 1;
+        0
+    }
+
+    /// Moves on to the next unexplored path. Returns `false` once every
+    /// path has been explored.
+    fn advance(&mut self) -> bool {
+        self.pos = 0;
+        while let Some((chosen, options)) = self.branches.pop() {
+            if chosen + 1 < options {
+                self.branches.push((chosen + 1, options));
+                return true;
+            }
+        }
+        false
+    }
+}
+
+struct Execution {
+    /// The real time at which the execution started, and virtual time
+    /// zero.
+    start: Instant,
+    sched: sync::Mutex<Sched>,
+    /// Signaled whenever `active` changes, or the execution is aborted.
+    baton: Condvar,
+    handles: sync::Mutex<Vec<os::JoinHandle<()>>>,
+}
+
+struct Sched {
+    /// The only model thread allowed to run.
+    active: usize,
+    threads: Vec<Run>,
+    /// Unpark tokens, as in `std::thread::park`.
+    tokens: Vec<bool>,
+    path: Path,
+    preemptions: usize,
+    preemption_bound: Option<usize>,
+    /// The virtual time since the start of the execution.
+    elapsed: Duration,
+    /// The threads that ran, in order, for the failure report.
+    trace: Vec<usize>,
+    failure: Option<String>,
+    aborted: bool,
+}
+
+impl Execution {
+    fn new(path: Path, preemption_bound: Option<usize>) -> Execution {
+        Execution {
+            start: Instant::now(),
+            sched: sync::Mutex::new(Sched {
+                active: 0,
+                threads: vec![Run::Runnable],
+                tokens: vec![false],
+                path,
+                preemptions: 0,
+                preemption_bound,
+                elapsed: Duration::ZERO,
+                trace: vec![0],
+                failure: None,
+                aborted: false,
+            }),
+            baton: Condvar::new(),
+            handles: sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    fn lock(&self) -> sync::MutexGuard<'_, Sched> {
+        self.sched.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs `body` as model thread `me`, on the current OS thread.
+    fn run(self: &Arc<Self>, me: usize, body: impl FnOnce()) {
+        CURRENT.with(|current| *current.borrow_mut() = Some((Arc::clone(self), me)));
+        let res = {
+            let sched = self.wait_for_turn(self.lock(), me);
+            let aborted = sched.aborted;
+            drop(sched);
+            if aborted {
+                Ok(())
+            } else {
+                panic::catch_unwind(AssertUnwindSafe(body))
+            }
+        };
+        CURRENT.with(|current| *current.borrow_mut() = None);
+
+        let mut sched = self.lock();
+        if let Err(payload) = res {
+            if !payload.is::<Aborted>() {
+                self.abort(
+                    &mut sched,
+                    format!("thread {} panicked: {}", me, message(&*payload)),
+                );
+            }
+        }
+        sched.threads[me] = Run::Finished;
+        for run in sched.threads.iter_mut() {
+            if *run == Run::Joining(me) {
+                *run = Run::Runnable;
+            }
+        }
+        if !sched.aborted {
+            self.switch(sched, me);
+        }
+    }
+
+    /// A scheduling point of the running thread `me`.
+    fn yield_now(&self, me: usize) {
+        let sched = self.lock();
+        self.switch(sched, me);
+    }
+
+    /// Parks `me` unless it has an unpark token.
+    fn park(&self, me: usize) {
+        let mut sched = self.lock();
+        if sched.tokens[me] {
+            sched.tokens[me] = false;
+        } else {
+            sched.threads[me] = Run::Parked;
+        }
+        self.switch(sched, me);
+    }
+
+    /// Parks `me` unless it has an unpark token, for at most `timeout` of
+    /// virtual time.
+    fn park_timeout(&self, me: usize, timeout: Duration) {
+        let mut sched = self.lock();
+        if sched.tokens[me] {
+            sched.tokens[me] = false;
+        } else {
+            sched.threads[me] = Run::ParkedUntil(sched.elapsed.saturating_add(timeout));
+        }
+        self.switch(sched, me);
+    }
+
+    fn unpark(&self, me: usize, thread: usize) {
+        let mut sched = self.lock();
+        match sched.threads[thread] {
+            Run::Parked | Run::ParkedUntil(_) => sched.threads[thread] = Run::Runnable,
+            _ => sched.tokens[thread] = true,
+        }
+        self.switch(sched, me);
+    }
+
+    /// Blocks `me` until the mutex at `addr` is unlocked.
+    fn block_on_lock(&self, me: usize, addr: usize) {
+        let mut sched = self.lock();
+        sched.threads[me] = Run::Locking(addr);
+        self.switch(sched, me);
+    }
+
+    /// Lets the threads waiting for the mutex at `addr` try again.
+    fn unlocked(&self, me: usize, addr: usize) {
+        let mut sched = self.lock();
+        for run in sched.threads.iter_mut() {
+            if *run == Run::Locking(addr) {
+                *run = Run::Runnable;
+            }
+        }
+        self.switch(sched, me);
+    }
+
+    /// Picks the next thread to run, hands it the baton, and waits for `me`
+    /// to be picked again, unless `me` has finished.
+    fn switch(&self, mut sched: sync::MutexGuard<'_, Sched>, me: usize) {
+        if sched.aborted {
+            drop(sched);
+            return bail();
+        }
+        // Threads in a timed park can be picked as well, which times them
+        // out.
+        let ready = |t: &usize| matches!(sched.threads[*t], Run::Runnable | Run::ParkedUntil(_));
+        let me_runnable = sched.threads[me] == Run::Runnable;
+        let me_finished = sched.threads[me] == Run::Finished;
+        // Staying on the current thread comes first, so that executions
+        // without preemptions are explored first.
+        let mut options: Vec<usize> = Some(me).into_iter().filter(ready).collect();
+        let may_preempt = sched
+            .preemption_bound
+            .is_none_or(|bound| sched.preemptions < bound);
+        if !me_runnable || may_preempt {
+            options.extend((0..sched.threads.len()).filter(|t| *t != me).filter(ready));
+        }
+        if options.is_empty() {
+            if sched.threads.iter().any(|run| *run != Run::Finished) {
+                let blocked = sched.threads.clone();
+                self.abort(&mut sched, format!("deadlock: {:?}", blocked));
+                if !me_finished {
+                    drop(sched);
+                    bail();
+                }
+            }
+            return;
+        }
+
+        let next = options[sched.path.choose(options.len())];
+        if let Run::ParkedUntil(deadline) = sched.threads[next] {
+            sched.elapsed = sched.elapsed.max(deadline);
+            sched.threads[next] = Run::Runnable;
+        }
+        if next != me {
+            if me_runnable {
+                sched.preemptions += 1;
+            }
+            sched.trace.push(next);
+            sched.active = next;
+            self.baton.notify_all();
+        }
+        if !me_finished {
+            let sched = self.wait_for_turn(sched, me);
+            if sched.aborted {
+                drop(sched);
+                bail();
+            }
+        }
+    }
+
+    fn wait_for_turn<'a>(
+        &self,
+        mut sched: sync::MutexGuard<'a, Sched>,
+        me: usize,
+    ) -> sync::MutexGuard<'a, Sched> {
+        while sched.active != me && !sched.aborted {
+            sched = self
+                .baton
+                .wait(sched)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        sched
+    }
+
+    /// Records the first failure, and lets every thread unwind.
+    fn abort(&self, sched: &mut Sched, failure: String) {
+        if sched.failure.is_none() {
+            sched.failure = Some(failure);
+        }
+        sched.aborted = true;
+        self.baton.notify_all();
+    }
+}
+
+/// Unwinds the current thread out of an aborted execution. Threads that are
+/// unwinding already carry on, without the scheduler.
+fn bail() {
+    if !os::panicking() {
+        panic::resume_unwind(Box::new(Aborted));
+    }
+}
+
+fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&'static str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// `std::sync::atomic::AtomicPtr`, with a scheduling point before every
+/// operation.
+pub(crate) struct AtomicPtr<T>(std::sync::atomic::AtomicPtr<T>);
+
+impl<T> AtomicPtr<T> {
+    pub(crate) const fn new(ptr: *mut T) -> AtomicPtr<T> {
+        AtomicPtr(std::sync::atomic::AtomicPtr::new(ptr))
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut *mut T {
+        self.0.get_mut()
+    }
+
+    pub(crate) fn load(&self, order: Ordering) -> *mut T {
+        yield_now();
+        self.0.load(order)
+    }
+
+    pub(crate) fn store(&self, ptr: *mut T, order: Ordering) {
+        yield_now();
+        self.0.store(ptr, order)
+    }
+
+    pub(crate) fn swap(&self, ptr: *mut T, order: Ordering) -> *mut T {
+        yield_now();
+        self.0.swap(ptr, order)
+    }
+
+    pub(crate) fn compare_exchange(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<*mut T, *mut T> {
+        yield_now();
+        self.0.compare_exchange(current, new, success, failure)
+    }
+
+    /// Never fails spuriously, so that executions stay deterministic.
+    pub(crate) fn compare_exchange_weak(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<*mut T, *mut T> {
+        self.compare_exchange(current, new, success, failure)
+    }
+}
+
+/// `std::sync::atomic::AtomicBool`, with a scheduling point before every
+/// operation.
+pub(crate) struct AtomicBool(std::sync::atomic::AtomicBool);
+
+impl AtomicBool {
+    pub(crate) const fn new(value: bool) -> AtomicBool {
+        AtomicBool(std::sync::atomic::AtomicBool::new(value))
+    }
+
+    pub(crate) fn load(&self, order: Ordering) -> bool {
+        yield_now();
+        self.0.load(order)
+    }
+
+    pub(crate) fn store(&self, value: bool, order: Ordering) {
+        yield_now();
+        self.0.store(value, order)
+    }
+}
+
+/// `std::sync::Mutex`, which blocks model threads through the scheduler
+/// rather than the OS, with a scheduling point before every lock and after
+/// every unlock.
+pub(crate) struct Mutex<T>(sync::Mutex<T>);
+
+pub(crate) struct MutexGuard<'a, T> {
+    lock: &'a Mutex<T>,
+    /// Only `None` while being dropped.
+    guard: Option<sync::MutexGuard<'a, T>>,
+}
+
+impl<T> Mutex<T> {
+    pub(crate) const fn new(value: T) -> Mutex<T> {
+        Mutex(sync::Mutex::new(value))
+    }
+
+    pub(crate) fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        let wrap = |guard| MutexGuard {
+            lock: self,
+            guard: Some(guard),
+        };
+        let (exec, me) = match current() {
+            Some(current) => current,
+            None => {
+                return self
+                    .0
+                    .lock()
+                    .map(wrap)
+                    .map_err(|err| PoisonError::new(wrap(err.into_inner())))
+            }
+        };
+        loop {
+            exec.yield_now(me);
+            match self.0.try_lock() {
+                Ok(guard) => return Ok(wrap(guard)),
+                Err(TryLockError::Poisoned(err)) => {
+                    return Err(PoisonError::new(wrap(err.into_inner())))
+                }
+                Err(TryLockError::WouldBlock) => exec.block_on_lock(me, self.addr()),
+            }
+        }
+    }
+
+    fn addr(&self) -> usize {
+        (self as *const Mutex<T>).addr()
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap_or_else(|| unreachable!())
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap_or_else(|| unreachable!())
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        if let Some((exec, me)) = current() {
+            exec.unlocked(me, self.lock.addr());
+        }
+    }
+}
+
+/// `std::thread`, with parking routed through the scheduler on model
+/// threads.
+pub(crate) mod thread {
+    use super::Execution;
+    use std::sync::Arc;
+    use std::thread as os;
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    pub(crate) struct Thread(Inner);
+
+    #[derive(Clone)]
+    enum Inner {
+        Os(os::Thread),
+        Model(Arc<Execution>, usize),
+    }
+
+    pub(crate) fn current() -> Thread {
+        match super::current() {
+            Some((exec, me)) => Thread(Inner::Model(exec, me)),
+            None => Thread(Inner::Os(os::current())),
+        }
+    }
+
+    pub(crate) fn park() {
+        match super::current() {
+            Some((exec, me)) => exec.park(me),
+            None => os::park(),
+        }
+    }
+
+    /// Parks for at most `timeout` of virtual time on model threads.
+    pub(crate) fn park_timeout(timeout: Duration) {
+        match super::current() {
+            Some((exec, me)) => exec.park_timeout(me, timeout),
+            None => os::park_timeout(timeout),
+        }
+    }
+
+    impl Thread {
+        pub(crate) fn unpark(&self) {
+            match &self.0 {
+                Inner::Os(thread) => thread.unpark(),
+                Inner::Model(exec, thread) => {
+                    let (_, me) =
+                        super::current().expect("model thread unparked from outside of the model");
+                    exec.unpark(me, *thread);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,367 @@
+//! Thread-safe, non-blocking, "first one wins" flavor of `OnceCell`.
+//!
+//! If two threads race to initialize a type from the `race` module, they
+//! don't block: both run their initializer, but only one of them gets to
+//! store its result. The loser's value is dropped, and both threads end up
+//! observing the winner's.
+//!
+//! This module does not require `std`, and never parks a thread, which makes
+//! it suitable for hot paths where running an initializer twice is cheaper
+//! than coordinating. Each type shares the `get`/`set`/`get_or_init`
+//! vocabulary of [`sync::OnceCell`](crate::sync::OnceCell).
+//!
+//! # Example
+//!
+//! ```
+//! use our_once_cell::race::OnceBool;
+//!
+//! static HAS_AVX: OnceBool = OnceBool::new();
+//!
+//! fn has_avx() -> bool {
+//!     HAS_AVX.get_or_init(|| cfg!(target_feature = "avx"))
+//! }
+//! # let _ = has_avx();
+//! ```
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use core::{fmt, marker::PhantomData, ptr, sync::atomic::AtomicPtr};
+use core::num::NonZeroUsize;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::Void;
+
+/// A thread-safe cell which can be written to only once, holding a
+/// [`NonZeroUsize`].
+#[derive(Default, Debug)]
+pub struct OnceNonZeroUsize {
+    inner: AtomicUsize,
+}
+
+impl OnceNonZeroUsize {
+    /// Creates a new empty cell.
+    pub const fn new() -> OnceNonZeroUsize {
+        OnceNonZeroUsize {
+            inner: AtomicUsize::new(0),
+        }
+    }
+
+    /// Gets the underlying value.
+    ///
+    /// Returns `None` if the cell is empty.
+    pub fn get(&self) -> Option<NonZeroUsize> {
+        NonZeroUsize::new(self.inner.load(Ordering::Acquire))
+    }
+
+    /// Sets the contents of this cell to `value`.
+    ///
+    /// Returns `Ok(())` if the cell was empty and `Err(value)` if it was
+    /// full.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::num::NonZeroUsize;
+    /// use our_once_cell::race::OnceNonZeroUsize;
+    ///
+    /// let one = NonZeroUsize::new(1).unwrap();
+    /// let two = NonZeroUsize::new(2).unwrap();
+    ///
+    /// let cell = OnceNonZeroUsize::new();
+    /// assert_eq!(cell.set(one), Ok(()));
+    /// assert_eq!(cell.set(two), Err(two));
+    /// assert_eq!(cell.get(), Some(one));
+    /// ```
+    pub fn set(&self, value: NonZeroUsize) -> Result<(), NonZeroUsize> {
+        let exchange =
+            self.inner
+                .compare_exchange(0, value.get(), Ordering::AcqRel, Ordering::Acquire);
+        match exchange {
+            Ok(_) => Ok(()),
+            Err(_) => Err(value),
+        }
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell
+    /// was empty.
+    ///
+    /// If several threads concurrently run `get_or_init`, more than one `f`
+    /// can be called. However, all threads will return the same value,
+    /// produced by some `f`.
+    pub fn get_or_init<F>(&self, f: F) -> NonZeroUsize
+    where
+        F: FnOnce() -> NonZeroUsize,
+    {
+        match self.get_or_try_init(|| Ok::<NonZeroUsize, Void>(f())) {
+            Ok(val) => val,
+            Err(void) => match void {},
+        }
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell
+    /// was empty. If the cell was empty and `f` failed, an error is
+    /// returned.
+    ///
+    /// If several threads concurrently run `get_or_try_init`, more than one
+    /// `f` can be called. However, all threads will return the same value,
+    /// produced by some `f`.
+    pub fn get_or_try_init<F, E>(&self, f: F) -> Result<NonZeroUsize, E>
+    where
+        F: FnOnce() -> Result<NonZeroUsize, E>,
+    {
+        match self.get() {
+            Some(val) => Ok(val),
+            None => {
+                let mut val = f()?;
+                if let Err(old) =
+                    self.inner
+                        .compare_exchange(0, val.get(), Ordering::AcqRel, Ordering::Acquire)
+                {
+                    // We lost the race: `old` is the winner's non-zero value.
+                    val = NonZeroUsize::new(old).unwrap_or_else(|| unreachable!());
+                }
+                Ok(val)
+            }
+        }
+    }
+}
+
+/// A thread-safe cell which can be written to only once, holding a `bool`.
+#[derive(Default, Debug)]
+pub struct OnceBool {
+    inner: OnceNonZeroUsize,
+}
+
+impl OnceBool {
+    /// Creates a new empty cell.
+    pub const fn new() -> OnceBool {
+        OnceBool {
+            inner: OnceNonZeroUsize::new(),
+        }
+    }
+
+    /// Gets the underlying value.
+    ///
+    /// Returns `None` if the cell is empty.
+    pub fn get(&self) -> Option<bool> {
+        self.inner.get().map(OnceBool::from_usize)
+    }
+
+    /// Sets the contents of this cell to `value`.
+    ///
+    /// Returns `Ok(())` if the cell was empty and `Err(value)` if it was
+    /// full.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use our_once_cell::race::OnceBool;
+    ///
+    /// let cell = OnceBool::new();
+    /// assert_eq!(cell.set(false), Ok(()));
+    /// assert_eq!(cell.set(true), Err(true));
+    /// assert_eq!(cell.get(), Some(false));
+    /// ```
+    pub fn set(&self, value: bool) -> Result<(), bool> {
+        self.inner.set(OnceBool::to_usize(value)).map_err(|_| value)
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell
+    /// was empty.
+    ///
+    /// If several threads concurrently run `get_or_init`, more than one `f`
+    /// can be called. However, all threads will return the same value,
+    /// produced by some `f`.
+    pub fn get_or_init<F>(&self, f: F) -> bool
+    where
+        F: FnOnce() -> bool,
+    {
+        OnceBool::from_usize(self.inner.get_or_init(|| OnceBool::to_usize(f())))
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell
+    /// was empty. If the cell was empty and `f` failed, an error is
+    /// returned.
+    ///
+    /// If several threads concurrently run `get_or_try_init`, more than one
+    /// `f` can be called. However, all threads will return the same value,
+    /// produced by some `f`.
+    pub fn get_or_try_init<F, E>(&self, f: F) -> Result<bool, E>
+    where
+        F: FnOnce() -> Result<bool, E>,
+    {
+        self.inner
+            .get_or_try_init(|| f().map(OnceBool::to_usize))
+            .map(OnceBool::from_usize)
+    }
+
+    #[inline]
+    fn from_usize(value: NonZeroUsize) -> bool {
+        value.get() == 1
+    }
+
+    #[inline]
+    fn to_usize(value: bool) -> NonZeroUsize {
+        let value = if value { 1 } else { 2 };
+        NonZeroUsize::new(value).unwrap_or_else(|| unreachable!())
+    }
+}
+
+/// A thread-safe cell which can be written to only once, holding a boxed
+/// value.
+///
+/// Unlike [`sync::OnceCell`](crate::sync::OnceCell), the value lives on the
+/// heap, so the cell itself is a single atomic pointer. Requires the `alloc`
+/// feature.
+#[cfg(feature = "alloc")]
+pub struct OnceBox<T> {
+    inner: AtomicPtr<T>,
+    ghost: PhantomData<Option<Box<T>>>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Default for OnceBox<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: fmt::Debug> fmt::Debug for OnceBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(v) => f.debug_tuple("OnceBox").field(v).finish(),
+            None => f.write_str("OnceBox(Uninit)"),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Drop for OnceBox<T> {
+    fn drop(&mut self) {
+        let ptr = *self.inner.get_mut();
+        if !ptr.is_null() {
+            // SAFETY: the pointer came from `Box::into_raw`, and we own it.
+            drop(unsafe { Box::from_raw(ptr) })
+        }
+    }
+}
+
+// SAFETY: same reasoning as for `sync::OnceCell`: every thread gets a `&T`,
+// and the `Box<T>` stored by one thread is dropped by whichever owns the cell.
+#[cfg(feature = "alloc")]
+unsafe impl<T: Send + Sync> Sync for OnceBox<T> {}
+
+#[cfg(feature = "alloc")]
+impl<T> OnceBox<T> {
+    /// Creates a new empty cell.
+    pub const fn new() -> OnceBox<T> {
+        OnceBox {
+            inner: AtomicPtr::new(ptr::null_mut()),
+            ghost: PhantomData,
+        }
+    }
+
+    /// Gets a reference to the underlying value.
+    ///
+    /// Returns `None` if the cell is empty.
+    pub fn get(&self) -> Option<&T> {
+        let ptr = self.inner.load(Ordering::Acquire);
+        if ptr.is_null() {
+            return None;
+        }
+        // SAFETY: a non-null pointer was published with `Release`, and stays
+        // valid until the cell is dropped. It is never written through.
+        Some(unsafe { &*ptr })
+    }
+
+    /// Sets the contents of this cell to `value`.
+    ///
+    /// Returns `Ok(())` if the cell was empty and `Err(value)` if it was
+    /// full.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use our_once_cell::race::OnceBox;
+    ///
+    /// let cell = OnceBox::new();
+    /// assert_eq!(cell.set(Box::new(92)), Ok(()));
+    /// assert_eq!(cell.set(Box::new(62)), Err(Box::new(62)));
+    /// assert_eq!(cell.get(), Some(&92));
+    /// ```
+    pub fn set(&self, value: Box<T>) -> Result<(), Box<T>> {
+        let ptr = Box::into_raw(value);
+        let exchange =
+            self.inner
+                .compare_exchange(ptr::null_mut(), ptr, Ordering::AcqRel, Ordering::Acquire);
+        if exchange.is_err() {
+            // SAFETY: we still own the pointer we failed to publish.
+            let value = unsafe { Box::from_raw(ptr) };
+            return Err(value);
+        }
+        Ok(())
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell
+    /// was empty.
+    ///
+    /// If several threads concurrently run `get_or_init`, more than one `f`
+    /// can be called. However, all threads will return the same value,
+    /// produced by some `f`; the other values are dropped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use our_once_cell::race::OnceBox;
+    ///
+    /// static GREETING: OnceBox<String> = OnceBox::new();
+    ///
+    /// let greeting = GREETING.get_or_init(|| Box::new(String::from("hello")));
+    /// assert_eq!(greeting, "hello");
+    /// ```
+    pub fn get_or_init<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> Box<T>,
+    {
+        match self.get_or_try_init(|| Ok::<Box<T>, Void>(f())) {
+            Ok(val) => val,
+            Err(void) => match void {},
+        }
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell
+    /// was empty. If the cell was empty and `f` failed, an error is
+    /// returned.
+    ///
+    /// If several threads concurrently run `get_or_try_init`, more than one
+    /// `f` can be called. However, all threads will return the same value,
+    /// produced by some `f`; the other values are dropped.
+    pub fn get_or_try_init<F, E>(&self, f: F) -> Result<&T, E>
+    where
+        F: FnOnce() -> Result<Box<T>, E>,
+    {
+        let mut ptr = self.inner.load(Ordering::Acquire);
+
+        if ptr.is_null() {
+            let val = f()?;
+            ptr = Box::into_raw(val);
+            let exchange = self.inner.compare_exchange(
+                ptr::null_mut(),
+                ptr,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+            if let Err(old) = exchange {
+                // SAFETY: we lost the race, so nobody else has seen our
+                // pointer. Drop our value and use the winner's.
+                drop(unsafe { Box::from_raw(ptr) });
+                ptr = old;
+            }
+        };
+        // SAFETY: `ptr` is non-null, published with `Release`, and stays
+        // valid until the cell is dropped.
+        Ok(unsafe { &*ptr })
+    }
+}
@@ -0,0 +1,22 @@
+//! The atomics, locks, clock and thread parking used by the `std` backend
+//! of `sync::OnceCell`.
+//!
+//! Regular builds use the std types directly. Test builds swap in the
+//! wrappers of [`crate::model`], which behave the same except when called
+//! from a model thread, where every operation first yields to the
+//! deterministic scheduler, and time is virtual.
+
+#[cfg(not(test))]
+pub(crate) use std::sync::atomic::{AtomicBool, AtomicPtr};
+#[cfg(not(test))]
+pub(crate) use std::sync::Mutex;
+#[cfg(not(test))]
+pub(crate) use std::thread;
+
+#[cfg(not(test))]
+pub(crate) fn now() -> std::time::Instant {
+    std::time::Instant::now()
+}
+
+#[cfg(test)]
+pub(crate) use crate::model::{now, thread, AtomicBool, AtomicPtr, Mutex};
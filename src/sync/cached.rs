@@ -0,0 +1,209 @@
+//! A lazily initialized value that expires.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::OnceCell;
+
+/// A source of time for [`Cached`].
+///
+/// [`SystemClock`] is the one to use outside of tests, where a clock
+/// that is moved forward by hand makes expiry deterministic.
+pub trait Clock {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+/// The [`Clock`] of the operating system, through [`Instant::now`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+}
+
+/// A value which is initialized on the first access, and initialized
+/// again once it is older than its time-to-live.
+///
+/// Like [`Lazy`](super::Lazy), the first access blocks until the single running
+/// initializer has finished. After that, readers are never held up by
+/// a refresh: the first thread to find the value stale runs the
+/// initializer again and gets the new value, while concurrent readers
+/// keep getting the stale one until it is replaced. Values are handed out
+/// as `Arc<T>`, so a reader can keep using the value it got while it is
+/// being replaced.
+///
+/// The age of a value is counted from the moment its initializer was
+/// started. [`invalidate`](Cached::invalidate) makes the current value
+/// stale right away.
+///
+/// If the initializer panics, the panic is propagated, and the next
+/// access tries again. Until then, readers keep getting the stale value.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use our_once_cell::sync::Cached;
+///
+/// static TOKEN: Cached<String> = Cached::new(Duration::from_secs(300), || {
+///     // Ask the identity provider for a new token.
+///     String::from("token")
+/// });
+///
+/// assert_eq!(*TOKEN.get(), "token");
+/// TOKEN.invalidate();
+/// assert_eq!(*TOKEN.get(), "token");
+/// ```
+pub struct Cached<T, F = fn() -> T, C = SystemClock> {
+    current: OnceCell<Mutex<Entry<T>>>,
+    /// Set while a thread refreshes the value.
+    refreshing: AtomicBool,
+    /// Bumped by `invalidate`. A value loaded before the latest bump is
+    /// stale.
+    generation: AtomicUsize,
+    ttl: Duration,
+    init: F,
+    clock: C,
+}
+
+struct Entry<T> {
+    value: Arc<T>,
+    loaded_at: Instant,
+    generation: usize,
+}
+
+impl<T: fmt::Debug, F, C> fmt::Debug for Cached<T, F, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.current.get().map(|current| {
+            let entry = current.lock().unwrap_or_else(|err| err.into_inner());
+            Arc::clone(&entry.value)
+        });
+        f.debug_struct("Cached")
+            .field("value", &value)
+            .field("ttl", &self.ttl)
+            .field("init", &"..")
+            .finish()
+    }
+}
+
+impl<T, F> Cached<T, F> {
+    /// Creates a new cached value with the given time-to-live and
+    /// initializing function, on the [`SystemClock`].
+    pub const fn new(ttl: Duration, init: F) -> Cached<T, F> {
+        Cached::with_clock(ttl, init, SystemClock)
+    }
+}
+
+impl<T, F, C> Cached<T, F, C> {
+    /// Creates a new cached value with the given time-to-live and
+    /// initializing function, measuring time with `clock`.
+    pub const fn with_clock(ttl: Duration, init: F, clock: C) -> Cached<T, F, C> {
+        Cached {
+            current: OnceCell::new(),
+            refreshing: AtomicBool::new(false),
+            generation: AtomicUsize::new(0),
+            ttl,
+            init,
+            clock,
+        }
+    }
+
+    /// Makes the current value stale: the next access runs the
+    /// initializer again.
+    pub fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+impl<T, F: Fn() -> T, C: Clock> Cached<T, F, C> {
+    /// Returns the current value, initializing or refreshing it first if
+    /// needed.
+    ///
+    /// Blocks only on the very first access. Afterwards, if the value is
+    /// stale and another thread is already refreshing it, the stale value
+    /// is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use our_once_cell::sync::Cached;
+    ///
+    /// let cached = Cached::new(Duration::from_secs(60), || vec![1, 2, 3]);
+    /// assert_eq!(cached.get().len(), 3);
+    /// ```
+    pub fn get(&self) -> Arc<T> {
+        let current = self.current.get_or_init(|| Mutex::new(self.load()));
+        let stale = {
+            let entry = current.lock().unwrap_or_else(|err| err.into_inner());
+            if !self.is_stale(&entry) {
+                return Arc::clone(&entry.value);
+            }
+            Arc::clone(&entry.value)
+        };
+
+        if self
+            .refreshing
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return stale;
+        }
+        let _refreshing = Refreshing(&self.refreshing);
+
+        // Another thread may have refreshed the value since we looked.
+        let entry = current.lock().unwrap_or_else(|err| err.into_inner());
+        if !self.is_stale(&entry) {
+            return Arc::clone(&entry.value);
+        }
+        drop(entry);
+
+        let fresh = self.load();
+        let value = Arc::clone(&fresh.value);
+        *current.lock().unwrap_or_else(|err| err.into_inner()) = fresh;
+        value
+    }
+
+    /// Runs the initializer.
+    fn load(&self) -> Entry<T> {
+        let generation = self.generation.load(Ordering::Acquire);
+        let loaded_at = self.clock.now();
+        Entry {
+            value: Arc::new((self.init)()),
+            loaded_at,
+            generation,
+        }
+    }
+
+    fn is_stale(&self, entry: &Entry<T>) -> bool {
+        entry.generation != self.generation.load(Ordering::Acquire)
+            || self.clock.now().saturating_duration_since(entry.loaded_at) >= self.ttl
+    }
+}
+
+/// Lets another thread refresh a [`Cached`] once this one is done, or
+/// has panicked.
+struct Refreshing<'a>(&'a AtomicBool);
+
+impl Drop for Refreshing<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
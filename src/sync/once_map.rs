@@ -0,0 +1,159 @@
+//! A map of once cells, for values that are each computed once per key.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+use super::OnceCell;
+
+/// A map whose values are each computed at most once, on first use.
+///
+/// Every key gets its own [`OnceCell`]. Concurrent calls to
+/// [`get_or_init`](OnceMap::get_or_init) for the same key block on that
+/// cell while one of them runs the initializer, and calls for other keys
+/// proceed independently: the map itself is only locked to find or insert
+/// a cell, never while an initializer runs.
+///
+/// Values are never moved or dropped while the map is shared, so the
+/// returned references stay valid for as long as the map is borrowed.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::thread;
+/// use our_once_cell::sync::OnceMap;
+///
+/// let templates = OnceMap::new();
+/// let compiled = AtomicUsize::new(0);
+///
+/// thread::scope(|s| {
+///     for _ in 0..4 {
+///         s.spawn(|| {
+///             for name in ["index", "about"] {
+///                 let template = templates.get_or_init(name, || {
+///                     compiled.fetch_add(1, Ordering::Relaxed);
+///                     format!("<compiled {}>", name)
+///                 });
+///                 assert_eq!(template, &format!("<compiled {}>", name));
+///             }
+///         });
+///     }
+/// });
+///
+/// assert_eq!(compiled.load(Ordering::Relaxed), 2);
+/// assert_eq!(templates.get("index").map(String::as_str), Some("<compiled index>"));
+/// ```
+///
+/// # Thread safety
+///
+/// Like a [`OnceCell`], the map hands out `&V` to every thread, so it is
+/// only `Sync` when `V: Send + Sync`. A map holding `!Sync` values cannot
+/// be shared:
+///
+/// ```compile_fail,E0277
+/// use std::cell::Cell;
+/// use our_once_cell::sync::OnceMap;
+///
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<OnceMap<u32, Cell<u32>>>();
+/// ```
+pub struct OnceMap<K, V> {
+    /// The cells are boxed so that they stay put when the map grows.
+    cells: Mutex<HashMap<K, Box<OnceCell<V>>>>,
+    /// `Mutex` alone would make the map `Sync` for any `V: Send`, but
+    /// the map shares `&V` like the cells themselves do.
+    _cells: PhantomData<OnceCell<V>>,
+}
+
+impl<K, V> Default for OnceMap<K, V> {
+    fn default() -> Self {
+        OnceMap {
+            cells: Mutex::new(HashMap::new()),
+            _cells: PhantomData,
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for OnceMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cells = self.cells.lock().unwrap_or_else(|err| err.into_inner());
+        let values = cells.iter().filter_map(|(key, cell)| Some((key, cell.get()?)));
+        f.debug_map().entries(values).finish()
+    }
+}
+
+impl<K: Eq + Hash, V> OnceMap<K, V> {
+    /// Creates a new empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets a reference to the value for `key`.
+    ///
+    /// Returns `None` if the value has not been initialized yet. This
+    /// method never blocks on an initializer.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let cells = self.cells.lock().unwrap_or_else(|err| err.into_inner());
+        let cell: *const OnceCell<V> = &**cells.get(key)?;
+        drop(cells);
+        // SAFETY: as in `OnceMap::cell`.
+        unsafe { &*cell }.get()
+    }
+
+    /// Gets the value for `key`, initializing it with `f` if there was
+    /// none.
+    ///
+    /// Many threads may call `get_or_init` concurrently with the same
+    /// key, but only one `f` will run; the others block until the value
+    /// is available. Calls with other keys are not held up.
+    ///
+    /// # Panics
+    ///
+    /// If `f` panics, the panic is propagated to the caller, and the
+    /// value for `key` remains uninitialized.
+    pub fn get_or_init<F>(&self, key: K, f: F) -> &V
+    where
+        F: FnOnce() -> V,
+    {
+        self.cell(key).get_or_init(f)
+    }
+
+    /// Gets the value for `key`, initializing it with `f` if there was
+    /// none. If `f` fails, the error is returned and the value stays
+    /// uninitialized, so a later call may retry.
+    pub fn get_or_try_init<F, E>(&self, key: K, f: F) -> Result<&V, E>
+    where
+        F: FnOnce() -> Result<V, E>,
+    {
+        self.cell(key).get_or_try_init(f)
+    }
+
+    /// Removes the value for `key`, returning it if it was initialized.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let cells = self.cells.get_mut().unwrap_or_else(|err| err.into_inner());
+        cells.remove(key).and_then(|cell| cell.into_inner())
+    }
+
+    /// Finds the cell for `key`, inserting an empty one if there is none.
+    fn cell(&self, key: K) -> &OnceCell<V> {
+        let mut cells = self.cells.lock().unwrap_or_else(|err| err.into_inner());
+        let cell: *const OnceCell<V> = &**cells.entry(key).or_default();
+        // SAFETY: the cell is boxed, so it does not move when the map
+        // grows, and boxes are only removed or dropped through
+        // `&mut self`. While the map is shared, only shared references
+        // to the cell are ever taken.
+        unsafe { &*cell }
+    }
+}
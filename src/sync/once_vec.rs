@@ -0,0 +1,276 @@
+//! An append-only vector, for values that are each written once by any
+//! thread.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::panic::{RefUnwindSafe, UnwindSafe};
+use core::ptr;
+use core::slice;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use crate::buckets::{bucket_len, locate, BUCKETS};
+use crate::imp;
+
+/// An append-only vector that can be pushed to from many threads at
+/// once.
+///
+/// Like [`unsync::OnceVec`](crate::unsync::OnceVec), the vector grows by
+/// buckets, each twice as large as the previous one, that are never
+/// reallocated, so references returned by [`push`](OnceVec::push) and
+/// [`get`](OnceVec::get) stay valid while more elements are pushed. Its
+/// slots are not whole [`OnceCell`](super::OnceCell)s, though: a slot is
+/// only ever written by the thread that reserved its index, so it only
+/// needs the state word of a cell next to the value, without an
+/// initializer or a panic policy.
+///
+/// Each push reserves its index with a single atomic increment, and reads
+/// are lock-free: getting an element takes two `Acquire` loads and never
+/// waits for a writer. An element that another thread is still writing
+/// reads as `None`, and iteration skips it. No thread ever waits for
+/// another: when several threads push the first elements of a new bucket
+/// at once, each of them allocates it, and all but one throw their
+/// allocation away.
+///
+/// # Example
+///
+/// ```
+/// use std::thread;
+/// use our_once_cell::sync::OnceVec;
+///
+/// static SYMBOLS: OnceVec<String> = OnceVec::new();
+///
+/// let handles: Vec<_> = (0..4)
+///     .map(|t| thread::spawn(move || SYMBOLS.push(format!("sym{}", t)).0))
+///     .collect();
+/// for handle in handles {
+///     let index = handle.join().unwrap();
+///     assert!(SYMBOLS.get(index).unwrap().starts_with("sym"));
+/// }
+/// assert_eq!(SYMBOLS.iter().count(), 4);
+/// ```
+///
+/// # Thread safety
+///
+/// Like a [`OnceCell`](super::OnceCell), the vector hands out `&T` to every
+/// thread, so it is only `Sync` when `T: Send + Sync`:
+///
+/// ```compile_fail,E0277
+/// use std::cell::Cell;
+/// use our_once_cell::sync::OnceVec;
+///
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<OnceVec<Cell<u32>>>();
+/// ```
+pub struct OnceVec<T> {
+    /// The first slot of each bucket, or null if the bucket has not been
+    /// allocated yet. Bucket `i` holds `bucket_len(i)` slots.
+    buckets: [AtomicPtr<Slot<T>>; BUCKETS],
+    /// The number of indices handed out by `push`. The last few of them
+    /// may still be being written.
+    len: AtomicUsize,
+    /// The vector owns and drops `T`s.
+    _values: PhantomData<T>,
+}
+
+// SAFETY: `push` moves values in from any thread, and `Drop` drops them on
+// the thread that owns the vector, which makes it `Send` when `T` is.
+// Sharing it hands out `&T`s to every thread, which needs `T: Sync` on top.
+unsafe impl<T: Send> Send for OnceVec<T> {}
+unsafe impl<T: Send + Sync> Sync for OnceVec<T> {}
+
+impl<T: RefUnwindSafe + UnwindSafe> RefUnwindSafe for OnceVec<T> {}
+impl<T: UnwindSafe> UnwindSafe for OnceVec<T> {}
+
+/// A single element, written at most once by the thread that was handed
+/// its index.
+struct Slot<T> {
+    state: imp::State,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Slot<T> {
+    fn new() -> Slot<T> {
+        Slot {
+            state: imp::State::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    fn get(&self) -> Option<&T> {
+        if !self.state.is_complete() {
+            return None;
+        }
+        // SAFETY: the value was written before the state was completed, and
+        // is never written again.
+        Some(unsafe { (*self.value.get()).assume_init_ref() })
+    }
+
+    /// Writes `value` into the slot.
+    ///
+    /// # Safety
+    ///
+    /// Only one thread may ever write to a given slot.
+    unsafe fn write(&self, value: T) -> &T {
+        // Nobody else writes to the slot, so it cannot be running or done.
+        let guard = self.state.begin().unwrap_or_else(|_| unreachable!());
+        // Readers only look at the value once the state is completed, and we
+        // are the only writer.
+        let value = (*self.value.get()).write(value);
+        guard.complete();
+        value
+    }
+}
+
+impl<T> Drop for Slot<T> {
+    fn drop(&mut self) {
+        if self.state.is_complete() {
+            // SAFETY: a completed slot holds a value, and `&mut self` means
+            // nobody else is looking at it.
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+impl<T> Default for OnceVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> OnceVec<T> {
+    /// Creates a new empty vector. Nothing is allocated until the first
+    /// push.
+    pub const fn new() -> Self {
+        OnceVec {
+            buckets: [const { AtomicPtr::new(ptr::null_mut()) }; BUCKETS],
+            len: AtomicUsize::new(0),
+            _values: PhantomData,
+        }
+    }
+
+    /// Returns the number of elements in the vector, including those
+    /// that other threads are still pushing.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` if the vector contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends `value` to the vector, returning its index and a
+    /// reference to it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of elements overflows `usize`.
+    pub fn push(&self, value: T) -> (usize, &T) {
+        let index = self.len.fetch_add(1, Ordering::AcqRel);
+        let (bucket, offset) = locate(index).expect("OnceVec: capacity overflow");
+        let slot = &self.bucket(bucket)[offset];
+        // SAFETY: nobody else was handed `index`, so the slot is ours to
+        // fill.
+        (index, unsafe { slot.write(value) })
+    }
+
+    /// Gets a reference to the element at `index`, or `None` if there is
+    /// none yet. This method never blocks.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let (bucket, offset) = locate(index)?;
+        let first = self.buckets[bucket].load(Ordering::Acquire);
+        if first.is_null() {
+            return None;
+        }
+        // SAFETY: the bucket is allocated, and `offset` is within it.
+        unsafe { &*first.add(offset) }.get()
+    }
+
+    /// Returns an iterator over the elements, in index order.
+    ///
+    /// Elements that are still being pushed by other threads are
+    /// skipped, and elements pushed while iterating may or may not be
+    /// visited.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { vec: self, index: 0 }
+    }
+
+    /// Returns the slots of `bucket`, allocating them if needed.
+    fn bucket(&self, bucket: usize) -> &[Slot<T>] {
+        let len = bucket_len(bucket);
+        let mut first = self.buckets[bucket].load(Ordering::Acquire);
+        if first.is_null() {
+            let slots: Box<[Slot<T>]> = (0..len).map(|_| Slot::new()).collect();
+            let new = Box::into_raw(slots) as *mut Slot<T>;
+            first = match self.buckets[bucket].compare_exchange(
+                ptr::null_mut(),
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => new,
+                Err(current) => {
+                    // SAFETY: another thread allocated the bucket first, so
+                    // nobody ever saw ours.
+                    drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(new, len)) });
+                    current
+                }
+            };
+        }
+        // SAFETY: allocated buckets are never freed before the vector.
+        unsafe { slice::from_raw_parts(first, len) }
+    }
+}
+
+impl<T> Drop for OnceVec<T> {
+    fn drop(&mut self) {
+        for (bucket, first) in self.buckets.iter_mut().enumerate() {
+            let first = *first.get_mut();
+            if !first.is_null() {
+                let slots = ptr::slice_from_raw_parts_mut(first, bucket_len(bucket));
+                // SAFETY: the bucket was allocated by `bucket` as a boxed
+                // slice of that length.
+                drop(unsafe { Box::from_raw(slots) });
+            }
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a OnceVec<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+/// An iterator over the elements of a [`OnceVec`], created by
+/// [`OnceVec::iter`].
+#[derive(Debug)]
+pub struct Iter<'a, T> {
+    vec: &'a OnceVec<T>,
+    index: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        while self.index < self.vec.len() {
+            let index = self.index;
+            self.index += 1;
+            if let Some(value) = self.vec.get(index) {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,195 @@
+//! A one-shot channel built on a shared once cell.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use super::OnceCell;
+use crate::imp;
+
+/// Creates a one-shot channel: a [`Promise`] to be fulfilled once, and a
+/// [`Completion`] through which any number of readers get the value.
+///
+/// Both halves share a [`OnceCell`]. If the promise is dropped without
+/// being fulfilled, readers get a [`Broken`] error instead of waiting
+/// forever.
+///
+/// # Example
+///
+/// ```
+/// use std::thread;
+/// use our_once_cell::sync::{promise, Broken};
+///
+/// let (answer, completion) = promise();
+/// let worker = thread::spawn(move || answer.fulfil(92));
+/// assert_eq!(completion.wait(), Ok(&92));
+/// worker.join().unwrap();
+///
+/// let (answer, completion) = promise::<i32>();
+/// drop(answer);
+/// assert_eq!(completion.wait(), Err(Broken));
+/// ```
+pub fn promise<T>() -> (Promise<T>, Completion<T>) {
+    let cell = Arc::new(OnceCell::new());
+    let completion = Completion {
+        cell: Arc::clone(&cell),
+        waiter: None,
+    };
+    (Promise { cell }, completion)
+}
+
+/// The writing half of a [`promise`].
+///
+/// Dropping it without calling [`fulfil`](Promise::fulfil) breaks the
+/// promise.
+pub struct Promise<T> {
+    cell: Arc<OnceCell<Result<T, Broken>>>,
+}
+
+impl<T> Promise<T> {
+    /// Fulfils the promise with `value`, waking up every reader.
+    pub fn fulfil(self, value: T) {
+        // Only the promise writes to the cell, and it is consumed here,
+        // so the cell is still empty. `drop` then finds it full.
+        let _ = self.cell.set(Ok(value));
+    }
+}
+
+impl<T> Drop for Promise<T> {
+    fn drop(&mut self) {
+        let _ = self.cell.set(Err(Broken));
+    }
+}
+
+impl<T> fmt::Debug for Promise<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Promise { .. }")
+    }
+}
+
+/// The reading half of a [`promise`].
+///
+/// Completions can be cloned and shared freely. Besides the blocking
+/// [`wait`](Completion::wait), a completion is a future resolving to a
+/// clone of the value.
+///
+/// ```
+/// use std::thread;
+/// use our_once_cell::sync::{promise, Broken, Completion};
+///
+/// async fn consume(completion: Completion<String>) -> Result<usize, Broken> {
+///     Ok(completion.await?.len())
+/// }
+///
+/// let (answer, completion) = promise();
+/// let reader = completion.clone();
+/// let worker = thread::spawn(move || answer.fulfil(String::from("done")));
+///
+/// assert_eq!(block_on(consume(reader)), Ok(4));
+/// assert_eq!(completion.wait().map(String::as_str), Ok("done"));
+/// worker.join().unwrap();
+/// # // A minimal executor that parks the thread until the future is woken.
+/// # fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+/// #     use std::sync::Arc;
+/// #     use std::task::{Context, Poll, Wake, Waker};
+/// #     struct ThreadWaker(thread::Thread);
+/// #     impl Wake for ThreadWaker {
+/// #         fn wake(self: Arc<Self>) {
+/// #             self.0.unpark();
+/// #         }
+/// #     }
+/// #     let mut fut = Box::pin(fut);
+/// #     let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+/// #     let mut cx = Context::from_waker(&waker);
+/// #     loop {
+/// #         match fut.as_mut().poll(&mut cx) {
+/// #             Poll::Ready(output) => return output,
+/// #             Poll::Pending => thread::park(),
+/// #         }
+/// #     }
+/// # }
+/// ```
+pub struct Completion<T> {
+    cell: Arc<OnceCell<Result<T, Broken>>>,
+    /// Our place in the cell's queue, once we have been polled.
+    waiter: Option<imp::WaiterRef>,
+}
+
+impl<T> Clone for Completion<T> {
+    fn clone(&self) -> Self {
+        Completion {
+            cell: Arc::clone(&self.cell),
+            waiter: None,
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Completion<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_get() {
+            Some(res) => f.debug_tuple("Completion").field(&res).finish(),
+            None => f.write_str("Completion(Pending)"),
+        }
+    }
+}
+
+impl<T> Completion<T> {
+    /// Blocks the current thread until the promise is fulfilled or
+    /// broken.
+    pub fn wait(&self) -> Result<&T, Broken> {
+        self.cell.wait().as_ref().map_err(|&broken| broken)
+    }
+
+    /// Returns the outcome of the promise, or `None` if it is still
+    /// pending. This method never blocks.
+    pub fn try_get(&self) -> Option<Result<&T, Broken>> {
+        let res = self.cell.get()?;
+        Some(res.as_ref().map_err(|&broken| broken))
+    }
+
+    /// Returns the number of readers waiting for the promise.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`OnceCell::queued_waiters`].
+    #[cfg(test)]
+    pub(crate) unsafe fn queued_readers(&self) -> usize {
+        self.cell.queued_waiters()
+    }
+}
+
+impl<T: Clone> Future for Completion<T> {
+    type Output = Result<T, Broken>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<T, Broken>> {
+        let this = self.get_mut();
+        let cell = &*this.cell;
+        if let Some(res) = cell.get() {
+            return Poll::Ready(res.clone());
+        }
+        cell.state.poll_wait(&mut this.waiter, cx).map(|status| cell.finished(status).clone())
+    }
+}
+
+impl<T> Drop for Completion<T> {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter.take() {
+            self.cell.state.abandon(waiter);
+        }
+    }
+}
+
+/// The error reported by a [`Completion`] whose [`Promise`] was dropped
+/// without being fulfilled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Broken;
+
+impl fmt::Display for Broken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("promise dropped without being fulfilled")
+    }
+}
+
+impl std::error::Error for Broken {}
@@ -0,0 +1,129 @@
+//! Coalescing of concurrent computations that share a key.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use super::OnceCell;
+
+/// Coalesces concurrent computations of the same key into one.
+///
+/// The first caller of [`run`](SingleFlight::run) for a key computes the
+/// value in a [`OnceCell`] of its own. Callers that arrive with the same
+/// key while it is running block on that cell, and each receives a clone
+/// of the result. Once the computation is over, the key is forgotten: the
+/// next caller computes again, so nothing is retained beyond the callers
+/// that shared it.
+///
+/// To share errors as well, make `V` a `Result`.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::thread;
+/// use our_once_cell::sync::SingleFlight;
+///
+/// let requests = SingleFlight::new();
+/// let fetches = AtomicUsize::new(0);
+/// let fetch = |user: &str| -> Result<String, String> {
+///     fetches.fetch_add(1, Ordering::Relaxed);
+///     Ok(format!("profile of {}", user))
+/// };
+///
+/// thread::scope(|s| {
+///     for _ in 0..4 {
+///         s.spawn(|| {
+///             let profile = requests.run("alice", || fetch("alice"));
+///             assert_eq!(profile.as_deref(), Ok("profile of alice"));
+///         });
+///     }
+/// });
+///
+/// // The calls that overlapped shared a fetch, and none is in flight any
+/// // more.
+/// assert!((1..=4).contains(&fetches.load(Ordering::Relaxed)));
+/// assert_eq!(requests.in_flight(), 0);
+/// ```
+pub struct SingleFlight<K, V> {
+    calls: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        SingleFlight {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for SingleFlight<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let calls = self.calls.lock().unwrap_or_else(|err| err.into_inner());
+        f.debug_struct("SingleFlight").field("in_flight", &calls.len()).finish()
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SingleFlight<K, V> {
+    /// Creates a new `SingleFlight` with no calls in flight.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the result of `f`, or of the call already in flight for
+    /// `key`.
+    ///
+    /// If another thread is computing the value for `key`, this blocks
+    /// until it is done and returns a clone of its result, without
+    /// calling `f`. Otherwise `f` is called, and the result is handed to
+    /// every caller that joined in the meantime.
+    ///
+    /// # Panics
+    ///
+    /// If `f` panics, the panic is propagated to the caller, and one of
+    /// the callers that were waiting for it, if any, runs its own `f`
+    /// instead. Callers arriving after the panic start a new call.
+    pub fn run<F>(&self, key: K, f: F) -> V
+    where
+        F: FnOnce() -> V,
+    {
+        let cell = {
+            let mut calls = self.calls.lock().unwrap_or_else(|err| err.into_inner());
+            Arc::clone(calls.entry(key.clone()).or_default())
+        };
+        cell.get_or_init(|| {
+            let _landed = Landed {
+                flight: self,
+                key: &key,
+                cell: &cell,
+            };
+            f()
+        })
+        .clone()
+    }
+
+    /// Returns the number of keys with a call in flight.
+    pub fn in_flight(&self) -> usize {
+        self.calls.lock().unwrap_or_else(|err| err.into_inner()).len()
+    }
+}
+
+/// Forgets the call for `key` once `f` has returned or panicked, unless
+/// a newer call has already taken its place. Callers arriving from then
+/// on start a new call, while those already holding `cell` still get its
+/// value.
+struct Landed<'a, K: Eq + Hash, V> {
+    flight: &'a SingleFlight<K, V>,
+    key: &'a K,
+    cell: &'a Arc<OnceCell<V>>,
+}
+
+impl<K: Eq + Hash, V> Drop for Landed<'_, K, V> {
+    fn drop(&mut self) {
+        let mut calls = self.flight.calls.lock().unwrap_or_else(|err| err.into_inner());
+        if calls.get(self.key).is_some_and(|current| Arc::ptr_eq(current, self.cell)) {
+            calls.remove(self.key);
+        }
+    }
+}
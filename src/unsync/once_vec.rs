@@ -0,0 +1,133 @@
+//! An append-only vector, for values that are each written once.
+
+use alloc::boxed::Box;
+use core::cell::Cell;
+use core::fmt;
+
+use super::OnceCell;
+use crate::buckets::{bucket_len, locate, BUCKETS};
+
+/// An append-only vector that can be pushed to through a shared
+/// reference, on a single thread.
+///
+/// The vector is chunked storage of [`OnceCell`]s: buckets of cells that
+/// are allocated as the vector grows, each twice as large as the previous
+/// one, and never reallocated. A push fills the next empty cell, so
+/// elements are never moved, and references returned by
+/// [`push`](OnceVec::push) and [`get`](OnceVec::get) stay valid while more
+/// elements are pushed.
+///
+/// Nothing is locked or updated atomically. Use
+/// [`sync::OnceVec`](crate::sync::OnceVec) to push from several threads.
+///
+/// # Example
+///
+/// ```
+/// use our_once_cell::unsync::OnceVec;
+///
+/// let symbols = OnceVec::new();
+/// let (foo, foo_ref) = symbols.push(String::from("foo"));
+/// for i in 0..100 {
+///     symbols.push(format!("sym{}", i));
+/// }
+///
+/// assert_eq!(foo, 0);
+/// assert_eq!(foo_ref, "foo");
+/// assert_eq!(symbols.get(1).map(String::as_str), Some("sym0"));
+/// assert_eq!(symbols.iter().count(), 101);
+/// ```
+pub struct OnceVec<T> {
+    buckets: [OnceCell<Box<[OnceCell<T>]>>; BUCKETS],
+    len: Cell<usize>,
+}
+
+impl<T> Default for OnceVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> OnceVec<T> {
+    /// Creates a new empty vector. Nothing is allocated until the first
+    /// push.
+    pub const fn new() -> Self {
+        OnceVec {
+            buckets: [const { OnceCell::new() }; BUCKETS],
+            len: Cell::new(0),
+        }
+    }
+
+    /// Returns the number of elements in the vector.
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// Returns `true` if the vector contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends `value` to the vector, returning its index and a
+    /// reference to it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of elements overflows `usize`.
+    pub fn push(&self, value: T) -> (usize, &T) {
+        let index = self.len.get();
+        let (bucket, offset) = locate(index).expect("OnceVec: capacity overflow");
+        let bucket = self.buckets[bucket]
+            .get_or_init(|| (0..bucket_len(bucket)).map(|_| OnceCell::new()).collect());
+        let slot = &bucket[offset];
+        debug_assert!(slot.get().is_none());
+        let value = slot.get_or_init(move || value);
+        self.len.set(index + 1);
+        (index, value)
+    }
+
+    /// Gets a reference to the element at `index`, or `None` if there is
+    /// none.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let (bucket, offset) = locate(index)?;
+        self.buckets[bucket].get()?[offset].get()
+    }
+
+    /// Returns an iterator over the elements, in the order they were
+    /// pushed.
+    ///
+    /// Elements pushed while iterating are visited as well.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { vec: self, index: 0 }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a OnceVec<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+/// An iterator over the elements of a [`OnceVec`], created by
+/// [`OnceVec::iter`].
+#[derive(Debug)]
+pub struct Iter<'a, T> {
+    vec: &'a OnceVec<T>,
+    index: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        let value = self.vec.get(self.index)?;
+        self.index += 1;
+        Some(value)
+    }
+}